@@ -0,0 +1,240 @@
+//! Accruals, for expenses paid once that belong to several months.
+//!
+//! A transaction marked with `accrue: 2023-01..2023-12` has its expenses booked
+//! on the prepaid account instead. Then an equal part is moved from the
+//! prepaid account to the expenses at the end of each month in the range, in
+//! whichever grouping's period covers that date. Parts outside every grouping
+//! period stay on the prepaid account.
+
+use std::fmt;
+use serde::{
+  Serialize,
+  Deserialize,
+};
+use schemars::JsonSchema;
+use rust_decimal::Decimal;
+use time::{
+  Date,
+  Month,
+};
+
+use crate::types::*;
+
+/// An inclusive range of months, written as `2023-01..2023-12`.
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
+pub struct MonthRange {
+  pub start: (i32, Month),
+  pub end: (i32, Month),
+}
+fn parse_month(raw: &str) -> Option<(i32, Month)> {
+  let (year, month) = raw.trim().split_once('-')?;
+  Some((year.parse().ok()?, Month::try_from(month.parse::<u8>().ok()?).ok()?))
+}
+impl TryFrom<String> for MonthRange {
+  type Error = String;
+  fn try_from(raw: String) -> Result<Self, Self::Error> {
+    raw.split_once("..")
+      .and_then(|(start, end)| Some(MonthRange{ start: parse_month(start)?, end: parse_month(end)? }))
+      .filter(|r| (r.start.0, r.start.1 as u8) <= (r.end.0, r.end.1 as u8))
+      .ok_or_else(|| format!("Invalid month range {}, expected YYYY-MM..YYYY-MM", raw))
+  }
+}
+impl fmt::Display for MonthRange {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}-{:02}..{}-{:02}",
+      self.start.0, self.start.1 as u8,
+      self.end.0, self.end.1 as u8,
+    )
+  }
+}
+impl From<MonthRange> for String {
+  fn from(range: MonthRange) -> String {
+    range.to_string()
+  }
+}
+impl MonthRange {
+  /// The last day of every month in the range.
+  pub fn month_ends(&self) -> Vec<Date> {
+    let mut ends = Vec::new();
+    let (mut year, mut month) = self.start;
+    loop {
+      ends.push(Date::from_calendar_date(year, month, month.length(year)).unwrap());
+      if (year, month) == self.end { break; }
+      if month == Month::December { year += 1; }
+      month = month.next();
+    }
+    ends
+  }
+}
+
+/// Move the expenses of accrued transactions to the prepaid account and add
+/// the monthly allocations to the groupings covering them.
+pub fn accrue(groupings: &mut [Grouping], prepaid: Option<&str>, book: &RealBookkeeping) {
+  let mut allocations = Vec::new();
+  for grouping in groupings.iter_mut() {
+    for transaction in grouping.transactions_mut() {
+      let range = match transaction.accrue {
+        Some(range) => range,
+        None => continue,
+      };
+      let prepaid = prepaid.unwrap_or_else(|| panic!(
+        "Transaction {} is accrued but no prepaid_account is configured.", transaction.name
+      ));
+      // Realize a copy to get the expenses without any VAT
+      let expenses: Vec<(String, Decimal)> = transaction.clone().realize(0, book).transfers.into_iter()
+        .filter(|(account, _)| book.account_type(account) == Some(AccountType::Expense))
+        .collect()
+      ;
+      if expenses.is_empty() {
+        panic!("Transaction {} is accrued but has no expenses.", transaction.name);
+      }
+      if transaction.transfers.iter().any(|(a, _)| a == prepaid) {
+        panic!("Transaction {} is accrued but already transfers to {}.", transaction.name, prepaid);
+      }
+      // Each VAT coded expense gets a prepaid transfer of its own with its
+      // code, first so the codes stay in the order of the transfers. The
+      // expenses without VAT codes share one after them.
+      let codes = by_transfer(&transaction.transfers, &transaction.vat);
+      let mut transfers = Vec::new();
+      let mut vat = Vec::new();
+      let mut coded = Vec::new();
+      let mut uncoded = None;
+      for ((account, amount), code) in transaction.transfers.drain(..).zip(codes) {
+        let expense = book.account_type(&account) == Some(AccountType::Expense);
+        match (expense, code) {
+          (true, Some(code)) => {
+            coded.push((prepaid.to_owned(), amount));
+            vat.push((prepaid.to_owned(), code));
+          },
+          (true, None) => *uncoded.get_or_insert(Decimal::ZERO) += amount,
+          (false, code) => {
+            if let Some(code) = code { vat.push((account.clone(), code)); }
+            transfers.push((account, amount));
+          },
+        }
+      }
+      transfers.extend(coded);
+      transfers.extend(uncoded.map(|amount| (prepaid.to_owned(), amount)));
+      transaction.transfers = transfers;
+      transaction.vat = vat;
+
+      let ends = range.month_ends();
+      let months = Decimal::from(ends.len());
+      for (i, date) in ends.iter().enumerate() {
+        let mut transfers = Vec::new();
+        for (account, amount) in &expenses {
+          let part = if i + 1 == ends.len() {
+            amount - (amount / months).round_dp(2) * (months - Decimal::ONE)
+          } else {
+            (amount / months).round_dp(2)
+          };
+          transfers.push((account.clone(), part));
+          match transfers.iter_mut().find(|(a, _)| a == prepaid) {
+            Some((_, x)) => *x -= part,
+            None => transfers.push((prepaid.to_owned(), -part)),
+          }
+        }
+        allocations.push(Transaction{
+          name: transaction.name.clone(),
+          date: *date,
+          transfers,
+          vat: Vec::new(),
+          accrue: None,
+          reference: None,
+          references: Vec::new(),
+          comments: Default::default(),
+          generated_by: Some(format!("accrual: {} {}", transaction.name, range)),
+          source: None,
+        });
+      }
+    }
+  }
+  for allocation in allocations {
+    if let Some(grouping) = groupings.iter_mut()
+      .find(|g| g.period.is_some_and(|p| p.contains(allocation.date)))
+    {
+      grouping.transactions_mut().push(allocation);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::file_io::DummyFileIO;
+  use crate::vat::{
+    VatCode,
+    vat_report,
+  };
+
+  const BOOK: &str = "
+version: 1
+name: test
+accounts:
+  asset: [money, prepaid]
+  creditor: [vat_out_25, vat_out_12, vat_out_6, vat_in, vat_reverse]
+  expense: [food, software]
+vat:
+  output_25: vat_out_25
+  output_12: vat_out_12
+  output_6: vat_out_6
+  input: vat_in
+  reverse_charge: vat_reverse
+prepaid_account: prepaid
+account_sums: {}
+groupings:
+- name: Q1
+  period: {start: 2023-01-01, end: 2023-03-31}
+  transactions: !Inlined
+  - name: x
+    date: 2023-01-01
+    accrue: 2023-01..2023-03
+    transfers:
+      food: 112
+      software: 125
+      money: -237
+    vat:
+      food: vat12
+      software: vat25
+";
+
+  fn d(s: &str) -> Decimal {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn month_ends() {
+    let range = MonthRange::try_from("2023-11..2024-02".to_owned()).unwrap();
+    assert_eq!(range.month_ends().len(), 4);
+    assert_eq!(range.month_ends()[3], Date::from_calendar_date(2024, Month::February, 29).unwrap());
+    assert!(MonthRange::try_from("2023-05..2023-04".to_owned()).is_err());
+  }
+
+  #[test]
+  fn accrued_mixed_vat() {
+    let book: Bookkeeping = serde_yaml::from_str(BOOK).unwrap();
+    let real = book.realize(&mut DummyFileIO{});
+    let transactions = &real.groupings[0].transactions;
+    let accrued = &transactions[0];
+    // A prepaid transfer per VAT code, each split by its own code
+    assert_eq!(accrued.transfers, vec![
+      ("money".to_owned(), d("-237")),
+      ("prepaid".to_owned(), d("100.00")),
+      ("prepaid".to_owned(), d("100.00")),
+      ("vat_in".to_owned(), d("37.00")),
+    ]);
+    let codes: Vec<(VatCode, Decimal)> = accrued.vat.iter().map(|l| (l.code, l.vat)).collect();
+    assert_eq!(codes, vec![(VatCode::Vat12, d("12.00")), (VatCode::Vat25, d("25.00"))]);
+    assert_eq!(vat_report(&real).total.box_48, d("37"));
+    // The net expenses are allocated over the months, to the cent
+    let food: Decimal = transactions[1..].iter()
+      .flat_map(|t| &t.transfers)
+      .filter(|(a, _)| a == "food")
+      .map(|(_, x)| *x)
+      .sum()
+    ;
+    assert_eq!(transactions.len(), 4);
+    assert_eq!(food, d("100"));
+  }
+}
@@ -0,0 +1,253 @@
+//! Adding new transactions to the files of the bookkeeping.
+//!
+//! The transaction is rendered as the fmt command would format it and then
+//! written into the grouping whose period covers its date. For groupings with
+//! transaction files it is appended to the last file, for inlined groupings it
+//! is inserted at the end of the list in bookkeeping.yaml. Nothing else in the
+//! files is touched, and nothing is written unless the bookkeeping is valid
+//! with the transaction added.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use rust_decimal::Decimal;
+use time::Date;
+
+use crate::types::*;
+use crate::file_io::{
+  FileIO,
+  PendingFileIO,
+};
+use crate::fmt::{
+  format_transactions,
+  indent,
+  is_comment_or_blank,
+  split_key,
+};
+
+/// A transaction as entered by hand, without any of the optional fields.
+pub fn new_transaction(name: &str, date: Date, transfers: Vec<(String, Decimal)>) -> Transaction {
+  Transaction{
+    name: name.to_owned(),
+    date,
+    transfers,
+    vat: Vec::new(),
+    accrue: None,
+    reference: None,
+    references: Vec::new(),
+    comments: Default::default(),
+    generated_by: None,
+    source: None,
+  }
+}
+
+/// The sum of the transfers, which must be 0 before saving.
+pub fn remaining(transfers: &[(String, Decimal)]) -> Decimal {
+  (-transfers.iter().map(|(_, amount)| amount).sum::<Decimal>()).normalize()
+}
+
+/// The declared accounts starting with what has been typed so far.
+pub fn complete<'a>(accounts: &'a BTreeSet<String>, typed: &str) -> Vec<&'a str> {
+  if let Some(account) = accounts.get(typed) { return vec![account.as_str()]; }
+  accounts.iter()
+    .filter(|a| a.starts_with(typed))
+    .map(|a| a.as_str())
+    .collect()
+}
+
+pub fn validate_new(transaction: &Transaction, accounts: &BTreeSet<String>) -> Result<(), String> {
+  if transaction.name.trim().is_empty() {
+    return Err("The transaction needs a name".to_owned());
+  }
+  if transaction.transfers.len() < 2 {
+    return Err("The transaction needs at least two transfers".to_owned());
+  }
+  for (account, _) in &transaction.transfers {
+    if !accounts.contains(account) {
+      return Err(format!("Account {} isn't declared", account));
+    }
+  }
+  let left = remaining(&transaction.transfers);
+  if !left.is_zero() {
+    return Err(format!("The transfers don't sum to 0, {} remains", left));
+  }
+  Ok(())
+}
+
+/// The transaction as a one item list, formatted as by the fmt command.
+pub fn render(transaction: &Transaction) -> String {
+  let raw = serde_yaml::to_string(&[transaction]).unwrap();
+  format_transactions(&raw).unwrap()
+}
+
+// The grouping whose period covers the date
+fn target_grouping(book: &Bookkeeping, date: Date) -> Result<&Grouping, String> {
+  book.groupings.iter()
+    .find(|g| g.period.is_some_and(|p| p.contains(date)))
+    .ok_or_else(|| format!("No grouping has a period covering {}, add one or give the groupings periods", date))
+}
+
+/// Where the inlined transactions of a grouping are in the root file, as the
+/// line after the `transactions:` key, the line after the list and the
+/// indentation of the list items.
+pub fn inlined_list(lines: &[&str], grouping: &str) -> Result<(usize, usize, usize), String> {
+  let not_found = || format!("Grouping {} not found in bookkeeping.yaml", grouping);
+  let no_inlined = || format!("Grouping {} has no inlined transactions", grouping);
+  // Only look at the keys of the groupings, not transactions with the same name
+  let groupings = lines.iter().position(|l| indent(l) == 0 && split_key(l).is_some_and(|(k, _)| k == "groupings"))
+    .ok_or_else(not_found)?
+  ;
+  let grouping_indent = lines[groupings + 1..].iter()
+    .find(|l| !is_comment_or_blank(l))
+    .map(|l| indent(l))
+    .ok_or_else(not_found)?
+  ;
+  let key_indent = grouping_indent + 2;
+  let key_at = |line: &str| -> Option<(String, String)> {
+    let plain = if indent(line) == grouping_indent { line.trim_start().strip_prefix("- ")? } else { line };
+    if indent(line) != grouping_indent && indent(plain) != key_indent { return None; }
+    split_key(plain).map(|(k, v)| (k.to_owned(), v.to_owned()))
+  };
+  let is_name = |line: &str| key_at(line).is_some_and(|(k, v)|
+    k == "name" && serde_yaml::from_str::<String>(&v).is_ok_and(|v| v == grouping)
+  );
+  // The grouping starts at its "- " line, which the name may come after
+  let name_line = (groupings + 1..lines.len()).find(|i| is_name(lines[*i])).ok_or_else(not_found)?;
+  let start = (groupings + 1..=name_line).rev()
+    .find(|i| indent(lines[*i]) == grouping_indent && lines[*i].trim_start().starts_with("- "))
+    .ok_or_else(not_found)?
+  ;
+  let mut i = start;
+  // Find the transactions key of the grouping
+  loop {
+    let line = lines.get(i).ok_or_else(no_inlined)?;
+    if i > start && !is_comment_or_blank(line) && indent(line) <= grouping_indent {
+      return Err(no_inlined());
+    }
+    if let Some((k, v)) = key_at(line) {
+      if k == "transactions" && v.starts_with("!Inlined") {
+        if v.trim_start_matches("!Inlined").trim() != "" {
+          return Err(format!("The transactions of grouping {} are on one line, add the first by hand", grouping));
+        }
+        break;
+      }
+    }
+    i += 1;
+  }
+  // The list items may be indented more or as much as the key
+  let mut item_indent = None;
+  let mut end = i + 1;
+  while let Some(line) = lines.get(end) {
+    if !is_comment_or_blank(line) {
+      let is_item = line.trim_start().starts_with("- ");
+      if indent(line) < key_indent || (indent(line) == key_indent && !is_item) { break; }
+      if is_item && item_indent.is_none() { item_indent = Some(indent(line)); }
+    }
+    end += 1;
+  }
+  // Leave comments and blank lines after the list where they are
+  while end > i + 1 && is_comment_or_blank(lines[end - 1]) { end -= 1; }
+  let item_indent = item_indent
+    .ok_or_else(|| format!("The transactions of grouping {} are empty, add the first by hand", grouping))?
+  ;
+  Ok((i + 1, end, item_indent))
+}
+
+/// Write the transaction into the grouping covering its date, if the check
+/// (such as calculating the bookkeeping) passes when reading the files as if
+/// it was written. Returns the file it was written to.
+pub fn add_transaction<T: FileIO>(
+  io: &mut T,
+  transaction: &Transaction,
+  check: impl FnOnce(&mut PendingFileIO<T>) -> Result<(), String>,
+) -> Result<PathBuf, String> {
+  let root_path = PathBuf::from("bookkeeping.yaml");
+  let root_raw = io.read_path(&root_path);
+  let book: Bookkeeping = serde_yaml::from_str(&root_raw)
+    .map_err(|e| format!("Invalid format at bookkeeping.yaml: {}", e))?
+  ;
+  let grouping = target_grouping(&book, transaction.date)?;
+  let rendered = render(transaction);
+  let (path, contents) = match &grouping.transactions {
+    Transactions::Paths(paths) => {
+      let path = paths.last()
+        .ok_or_else(|| format!("Grouping {} has no transaction files", grouping.name))?
+      ;
+      let mut raw = io.read_path(path);
+      if !raw.is_empty() && !raw.ends_with('\n') { raw.push('\n'); }
+      // Keep to blank lines between transactions if the file does
+      if raw.contains("\n\n- ") { raw.push('\n'); }
+      raw.push_str(&rendered);
+      (path.clone(), raw)
+    },
+    Transactions::Inlined(_) => {
+      let lines: Vec<&str> = root_raw.lines().collect();
+      let (_, end, item_indent) = inlined_list(&lines, &grouping.name)?;
+      let pad = " ".repeat(item_indent);
+      let mut out: Vec<String> = lines[..end].iter().map(|l| l.to_string()).collect();
+      out.extend(rendered.lines().map(|l| format!("{}{}", pad, l)));
+      out.extend(lines[end..].iter().map(|l| l.to_string()));
+      (root_path, out.join("\n") + "\n")
+    },
+  };
+  let mut pending = PendingFileIO{ inner: io, path, contents };
+  check(&mut pending).map_err(|e| format!("Not added, the bookkeeping would be invalid: {}", e))?;
+  let PendingFileIO{ inner, path, contents } = pending;
+  inner.write_path(&path, &contents)?;
+  Ok(path)
+}
+
+/// Ask for the transaction on the terminal, completing account names from
+/// their start. Returns None if the input ends.
+pub fn prompt_transaction(accounts: &BTreeSet<String>, today: Date) -> Option<Transaction> {
+  use std::io::Write;
+  let read = |prompt: &str| -> Option<String> {
+    print!("{}", prompt);
+    std::io::stdout().flush().unwrap();
+    let mut line = String::new();
+    match std::io::stdin().read_line(&mut line) {
+      Ok(0) | Err(_) => None,
+      Ok(_) => Some(line.trim().to_owned()),
+    }
+  };
+  let date = loop {
+    let raw = read(&format!("Date [{}]: ", today))?;
+    if raw.is_empty() { break today; }
+    match serde_yaml::from_str::<Date>(&raw) {
+      Ok(date) => break date,
+      Err(_) => println!("Invalid date {}, expected YYYY-MM-DD", raw),
+    }
+  };
+  let name = loop {
+    let name = read("Name: ")?;
+    if !name.is_empty() { break name; }
+  };
+  let mut transfers: Vec<(String, Decimal)> = Vec::new();
+  loop {
+    let left = remaining(&transfers);
+    println!("Remaining to balance: {}", left);
+    let typed = read("Account (empty when done): ")?;
+    if typed.is_empty() {
+      if transfers.len() >= 2 && left.is_zero() { break; }
+      println!("Can't save until there are at least two transfers summing to 0");
+      continue;
+    }
+    let account = match complete(accounts, &typed).as_slice() {
+      [account] => account.to_string(),
+      [] => { println!("No account matches {}", typed); continue; },
+      matches => { println!("Matching accounts: {}", matches.join(", ")); continue; },
+    };
+    let raw = read(&format!("Amount for {} [{}]: ", account, left))?;
+    let amount = if raw.is_empty() { left } else {
+      match raw.parse::<Decimal>() {
+        Ok(amount) => amount,
+        Err(_) => { println!("Invalid amount {}", raw); continue; },
+      }
+    };
+    if amount.is_zero() {
+      println!("The amount can't be 0");
+      continue;
+    }
+    transfers.push((account, amount));
+  }
+  Some(new_transaction(&name, date, transfers))
+}
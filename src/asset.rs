@@ -0,0 +1,265 @@
+//! Fixed asset register with depreciation.
+//!
+//! An asset links to the transaction that bought it, which should book the
+//! purchase on an asset account. Depreciation is then generated on the last
+//! day of every month of its useful life, starting with the month of purchase,
+//! into the groupings whose period covers it.
+
+use serde::{
+  Serialize,
+  Deserialize,
+};
+use schemars::JsonSchema;
+use rust_decimal::Decimal;
+use time::{
+  Date,
+  Duration,
+};
+
+use crate::types::*;
+use crate::schema::yaml_tags;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+#[schemars(transform = yaml_tags)]
+pub enum Depreciation {
+  /// The same amount every month
+  StraightLine,
+  /// A yearly percentage of the book value, the rest at the end of its life
+  DecliningBalance(Decimal),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Asset {
+  pub name: String,
+  // Name of the transaction that bought the asset
+  pub purchase: String,
+  // The asset account the purchase is booked on
+  pub account: String,
+  // Expense account the depreciation is booked on
+  pub depreciation_account: String,
+  // Account to accumulate the depreciation on, the asset account if not given
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub accumulated_account: Option<String>,
+  pub method: Depreciation,
+  // In years
+  pub useful_life: u32,
+  #[serde(default)]
+  pub residual_value: Decimal,
+}
+
+/// An asset with its purchase looked up and depreciation calculated.
+#[derive(Debug, PartialEq, Serialize, Clone)]
+pub struct RealAsset {
+  pub name: String,
+  pub acquired: Date,
+  pub value: Decimal,
+  pub depreciation_account: String,
+  pub accumulated_account: String,
+  pub depreciations: Vec<(Date, Decimal)>,
+}
+
+fn end_of_month(date: Date) -> Date {
+  date.replace_day(date.month().length(date.year())).unwrap()
+}
+
+impl Asset {
+  /// Find the purchase among the (read) groupings and calculate depreciation.
+  pub fn realize(&self, groupings: &[Grouping], book: &RealBookkeeping) -> RealAsset {
+    let mut purchases = groupings.iter()
+      .flat_map(|g| g.transactions())
+      .filter(|t| t.name == self.purchase)
+    ;
+    let purchase = purchases.next()
+      .unwrap_or_else(|| panic!("Purchase {} of asset {} not found.", self.purchase, self.name))
+    ;
+    if purchases.next().is_some() {
+      panic!("Purchase {} of asset {} is ambiguous, give it a unique name.", self.purchase, self.name);
+    }
+    // Realize to get the value without any VAT
+    let value = purchase.clone().realize(0, book).transfers.iter()
+      .filter(|(account, _)| account == &self.account)
+      .map(|(_, amount)| *amount)
+      .sum::<Decimal>()
+    ;
+    if value <= Decimal::ZERO {
+      panic!("Purchase {} of asset {} has no transfer to {}.", self.purchase, self.name, self.account);
+    }
+    if self.residual_value < Decimal::ZERO || self.residual_value >= value {
+      panic!("Asset {} has residual value {}, which must be at least 0 and below its value {}.", self.name, self.residual_value, value);
+    }
+    if self.useful_life == 0 {
+      panic!("Asset {} has no useful life, give it in whole years.", self.name);
+    }
+
+    let months = self.useful_life * 12;
+    let depreciable = value - self.residual_value;
+    let mut book_value = value;
+    let mut month = end_of_month(purchase.date);
+    let mut depreciations = Vec::new();
+    for i in 0..months {
+      let amount = if i + 1 == months {
+        book_value - self.residual_value
+      } else {
+        match &self.method {
+          Depreciation::StraightLine => (depreciable / Decimal::from(months)).round_dp(2),
+          Depreciation::DecliningBalance(rate) => (book_value * rate / Decimal::from(1200)).round_dp(2),
+        }
+      }.min(book_value - self.residual_value);
+      book_value -= amount;
+      depreciations.push((month, amount));
+      month = end_of_month(month + Duration::days(1));
+    }
+
+    RealAsset{
+      name: self.name.clone(),
+      acquired: purchase.date,
+      value,
+      depreciation_account: self.depreciation_account.clone(),
+      accumulated_account: self.accumulated_account.clone().unwrap_or_else(|| self.account.clone()),
+      depreciations,
+    }
+  }
+}
+
+/// The depreciation of all assets within the given period.
+pub fn depreciations(assets: &[RealAsset], period: &Period) -> Vec<Transaction> {
+  let mut transactions: Vec<Transaction> = assets.iter()
+    .flat_map(|asset| asset.depreciations.iter()
+      .filter(|(date, _)| period.contains(*date))
+      .map(move |(date, amount)| Transaction{
+        name: format!("Depreciation of {}", asset.name),
+        date: *date,
+        transfers: vec![
+          (asset.depreciation_account.clone(), *amount),
+          (asset.accumulated_account.clone(), -amount),
+        ],
+        vat: Vec::new(),
+        accrue: None,
+        reference: None,
+        references: Vec::new(),
+        comments: Default::default(),
+        generated_by: Some(format!("asset: {}", asset.name)),
+        source: None,
+      })
+    )
+    .collect()
+  ;
+  transactions.sort_by_key(|t| t.date);
+  transactions
+}
+
+#[derive(Debug, Serialize)]
+pub struct AssetLine {
+  pub name: String,
+  pub acquired: Date,
+  pub acquisition_value: Decimal,
+  pub accumulated_depreciation: Decimal,
+  pub book_value: Decimal,
+}
+
+/// The register of all assets, with depreciation up to and including `as_of`.
+pub fn asset_report(assets: &[RealAsset], as_of: Date) -> Vec<AssetLine> {
+  assets.iter().map(|asset| {
+    let accumulated = asset.depreciations.iter()
+      .filter(|(date, _)| *date <= as_of)
+      .map(|(_, amount)| *amount)
+      .sum::<Decimal>()
+    ;
+    AssetLine{
+      name: asset.name.clone(),
+      acquired: asset.acquired,
+      acquisition_value: asset.value,
+      accumulated_depreciation: accumulated,
+      book_value: asset.value - accumulated,
+    }
+  }).collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::file_io::DummyFileIO;
+
+  const BOOK: &str = "
+version: 1
+name: test
+accounts:
+  asset: [money, computers]
+  expense: [depreciation]
+account_sums: {}
+assets:
+- name: laptop
+  purchase: Buy laptop
+  account: computers
+  depreciation_account: depreciation
+  method: StraightLine
+  useful_life: 1
+groupings:
+- name: '2023'
+  period: {start: 2023-01-01, end: 2023-12-31}
+  transactions: !Inlined
+  - name: Buy laptop
+    date: 2023-01-15
+    transfers:
+      computers: 1000
+      money: -1000
+";
+
+  fn d(s: &str) -> Decimal {
+    s.parse().unwrap()
+  }
+
+  fn amounts(method: &str, residual: &str) -> Vec<Decimal> {
+    let raw = BOOK.replace("method: StraightLine", &format!("method: {}\n  residual_value: {}", method, residual));
+    let book: Bookkeeping = serde_yaml::from_str(&raw).unwrap();
+    let real = book.realize(&mut DummyFileIO{});
+    real.assets[0].depreciations.iter().map(|(_, amount)| *amount).collect()
+  }
+
+  #[test]
+  fn straight_line() {
+    let book: Bookkeeping = serde_yaml::from_str(BOOK).unwrap();
+    let real = book.realize(&mut DummyFileIO{});
+    let asset = &real.assets[0];
+    // From the end of the month of purchase, the last taking the rounding
+    assert_eq!(asset.depreciations.len(), 12);
+    assert_eq!(asset.depreciations[0], (Date::from_calendar_date(2023, time::Month::January, 31).unwrap(), d("83.33")));
+    assert_eq!(asset.depreciations[11].1, d("83.37"));
+    assert_eq!(amounts("StraightLine", "100"), vec![d("75"); 12]);
+    // Booked in the grouping as transactions
+    let generated: Vec<_> = real.groupings[0].transactions.iter()
+      .filter(|t| t.generated_by.as_deref() == Some("asset: laptop"))
+      .collect()
+    ;
+    assert_eq!(generated.len(), 12);
+  }
+
+  #[test]
+  fn declining_balance() {
+    let amounts = amounts("!DecliningBalance 30", "0");
+    assert_eq!(amounts[..3], [d("25.00"), d("24.38"), d("23.77")]);
+    // The rest at the end of its life
+    assert_eq!(amounts[11], d("756.90"));
+    assert_eq!(amounts.iter().sum::<Decimal>(), d("1000"));
+  }
+
+  #[test]
+  fn clamped_to_residual_value() {
+    let amounts = amounts("!DecliningBalance 30", "900");
+    assert_eq!(amounts[..6], [d("25.00"), d("24.38"), d("23.77"), d("23.17"), d("3.68"), d("0")]);
+    assert_eq!(amounts.iter().sum::<Decimal>(), d("100"));
+  }
+
+  #[test]
+  #[should_panic(expected = "Asset laptop has residual value 1200")]
+  fn residual_above_value() {
+    amounts("StraightLine", "1200");
+  }
+
+  #[test]
+  #[should_panic(expected = "Asset laptop has no useful life")]
+  fn no_useful_life() {
+    let book: Bookkeeping = serde_yaml::from_str(&BOOK.replace("useful_life: 1", "useful_life: 0")).unwrap();
+    book.realize(&mut DummyFileIO{});
+  }
+}
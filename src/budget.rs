@@ -0,0 +1,273 @@
+//! Budgets, and comparing them with the calculated sums.
+//!
+//! A budget file gives expected sums per account or account_sums category,
+//! either for a named grouping or per month. Budgeted amounts use the same
+//! signs as the bookkeeping, so an income budget is negative.
+
+use std::collections::{
+  BTreeMap,
+  BTreeSet,
+};
+use serde::{
+  Serialize,
+  Deserialize,
+};
+use schemars::JsonSchema;
+use rust_decimal::Decimal;
+
+use crate::types::*;
+use crate::calculate::*;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct Budget {
+  // Expected per month, multiplied by the number of months a grouping covers
+  #[serde(default)]
+  pub monthly: BTreeMap<String, Decimal>,
+  // Expected for the grouping with the given name, overrides monthly
+  #[serde(default)]
+  pub groupings: BTreeMap<String, BTreeMap<String, Decimal>>,
+}
+impl Budget {
+  /// Panic if the budget names accounts, sums or groupings that don't exist.
+  pub fn validate(&self, book: &RealBookkeeping) {
+    let known = |key: &String| {
+      book.accounts.contains(key) || book.account_sums.iter().any(|(s, _)| s == key)
+    };
+    for key in self.monthly.keys() {
+      if !known(key) { panic!("Budget for undeclared account or sum {}, invalid.", key); }
+    }
+    for (grouping, amounts) in &self.groupings {
+      if !book.groupings.iter().any(|g| &g.name == grouping) {
+        panic!("Budget for unknown grouping {}, invalid.", grouping);
+      }
+      for key in amounts.keys() {
+        if !known(key) { panic!("Budget for undeclared account or sum {}, invalid.", key); }
+      }
+    }
+  }
+
+  /// The expected sum for an account or sum in a grouping covering `months`.
+  pub fn expected(&self, grouping: &str, months: usize, key: &str) -> Option<Decimal> {
+    self.groupings.get(grouping)
+      .and_then(|amounts| amounts.get(key))
+      .copied()
+      .or_else(|| self.monthly.get(key).map(|x| x * Decimal::from(months)))
+  }
+
+  /// The expected sum over all groupings, if any grouping has one. Monthly
+  /// amounts are counted once per month covered by the groupings without an
+  /// amount of their own, however many groupings cover it.
+  pub fn expected_total(&self, summary: &SummedBookkeeping, key: &str) -> Option<Decimal> {
+    let overridden = |name: &String| self.groupings.get(name).is_some_and(|amounts| amounts.contains_key(key));
+    let fixed = summary.groupings.iter()
+      .filter(|(name, _)| overridden(name))
+      .map(|(name, _)| self.groupings[name][key])
+      .reduce(|a, b| a + b)
+    ;
+    let covered: BTreeSet<(i32, u8)> = summary.groupings.iter()
+      .filter(|(name, _)| overridden(name))
+      .flat_map(|(_, gs)| grouping_months(gs))
+      .collect()
+    ;
+    let months = summary.groupings.iter()
+      .filter(|(name, _)| !overridden(name))
+      .flat_map(|(_, gs)| grouping_months(gs))
+      .filter(|month| !covered.contains(month))
+      .collect::<BTreeSet<_>>()
+      .len()
+    ;
+    let monthly = self.monthly.get(key)
+      .filter(|_| summary.groupings.iter().any(|(name, _)| !overridden(name)))
+      .map(|x| x * Decimal::from(months))
+    ;
+    match (fixed, monthly) {
+      (Some(a), Some(b)) => Some(a + b),
+      (a, b) => a.or(b),
+    }
+  }
+}
+
+/// The calendar months a grouping covers, as year and month. From its period
+/// if given, otherwise the months its transfers fall within.
+pub fn grouping_months(gs: &SummedGrouping) -> BTreeSet<(i32, u8)> {
+  match gs.period {
+    Some(period) => {
+      let mut months = BTreeSet::new();
+      let (mut year, mut month) = (period.start.year(), period.start.month() as u8);
+      while (year, month) <= (period.end.year(), period.end.month() as u8) {
+        months.insert((year, month));
+        (year, month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+      }
+      months
+    },
+    None => gs.account_types.iter()
+      .flat_map(|(_, _, accounts)| accounts)
+      .flat_map(|account| &account.transfers)
+      .map(|transfer| (transfer.date.year(), transfer.date.month() as u8))
+      .collect(),
+  }
+}
+
+/// Number of calendar months a grouping covers.
+pub fn months(gs: &SummedGrouping) -> usize {
+  grouping_months(gs).len()
+}
+
+#[derive(Debug, Serialize)]
+pub struct BudgetLine {
+  pub name: String,
+  // If the name is an account with these given
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub number: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  pub budget: Decimal,
+  pub actual: Decimal,
+  // Budget minus actual, so positive means under budget for expenses
+  pub variance: Decimal,
+  // Actual as percent of budget, none if the budget is 0
+  pub used_percent: Option<Decimal>,
+}
+impl BudgetLine {
+  fn new(name: &str, info: Option<&AccountInfo>, budget: Decimal, actual: Decimal) -> Self {
+    Self{
+      name: name.to_owned(),
+      number: info.and_then(|i| i.number),
+      description: info.and_then(|i| i.description.clone()),
+      budget,
+      actual,
+      variance: budget - actual,
+      used_percent: if budget.is_zero() { None } else {
+        Some((actual / budget * Decimal::ONE_HUNDRED).round_dp(1))
+      },
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BudgetReport {
+  pub name: String,
+  pub total: Vec<BudgetLine>,
+  #[serde(with = "tuple_vec_map")]
+  pub groupings: Vec<(String, Vec<BudgetLine>)>,
+}
+
+impl SummedGrouping {
+  /// The sum of an account or account_sums category, 0 if it has no transfers.
+  pub fn actual(&self, key: &str) -> Decimal {
+    self.account_types.iter()
+      .flat_map(|(_, _, accounts)| accounts)
+      .find(|account| account.name == key)
+      .map(|account| account.sum)
+      .or_else(|| self.account_sums.iter().find(|(name, _, _)| name == key).map(|(_, sum, _)| *sum))
+      .unwrap_or(Decimal::ZERO)
+  }
+}
+
+/// Compare the budget with the calculated sums, per grouping and in total.
+pub fn budget_report(summary: &SummedBookkeeping) -> BudgetReport {
+  let budget = summary.budget.as_ref()
+    .expect("No budget is given in bookkeeping.yaml")
+  ;
+  let keys = budget.monthly.keys()
+    .chain(budget.groupings.values().flat_map(|amounts| amounts.keys()))
+    .collect::<BTreeSet<_>>()
+  ;
+  BudgetReport{
+    name: summary.name.clone(),
+    total: keys.iter()
+      .filter_map(|key| budget.expected_total(summary, key)
+        .map(|b| BudgetLine::new(key, summary.account_info.get(*key), b, summary.total.actual(key)))
+      )
+      .collect(),
+    groupings: summary.groupings.iter().map(|(name, gs)| {
+      let months = months(gs);
+      (name.clone(), keys.iter()
+        .filter_map(|key| budget.expected(name, months, key)
+          .map(|b| BudgetLine::new(key, summary.account_info.get(*key), b, gs.actual(key)))
+        )
+        .collect()
+      )
+    }).collect(),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use time::{
+    Date,
+    Month,
+  };
+
+  fn day(year: i32, month: Month, day: u8) -> Date {
+    Date::from_calendar_date(year, month, day).unwrap()
+  }
+
+  fn grouping(start: Date, end: Date) -> SummedGrouping {
+    SummedGrouping{
+      period: Some(Period{ start, end }),
+      account_types: Vec::new(),
+      account_sums: Vec::new(),
+      account_hierarchy: Vec::new(),
+      metrics: Vec::new(),
+    }
+  }
+
+  fn summary(groupings: Vec<(&str, SummedGrouping)>) -> SummedBookkeeping {
+    SummedBookkeeping{
+      name: "test".to_owned(),
+      budget: None,
+      account_info: BTreeMap::new(),
+      total: SummedGrouping{ period: None, ..grouping(day(2023, Month::January, 1), day(2023, Month::January, 1)) },
+      groupings: groupings.into_iter().map(|(n, g)| (n.to_owned(), g)).collect(),
+    }
+  }
+
+  #[test]
+  fn months_of_period() {
+    let gs = grouping(day(2022, Month::December, 15), day(2023, Month::February, 1));
+    assert_eq!(months(&gs), 3);
+    let gs = grouping(day(2023, Month::January, 1), day(2023, Month::January, 1));
+    assert_eq!(months(&gs), 1);
+  }
+
+  #[test]
+  fn months_without_transfers() {
+    // A quiet month still counts when the period covers it
+    let gs = grouping(day(2023, Month::January, 1), day(2023, Month::December, 31));
+    assert_eq!(months(&gs), 12);
+    let gs = SummedGrouping{ period: None, ..gs };
+    assert_eq!(months(&gs), 0);
+  }
+
+  #[test]
+  fn total_counts_months_once() {
+    let budget = Budget{
+      monthly: [("food".to_owned(), Decimal::from(150))].into(),
+      groupings: BTreeMap::new(),
+    };
+    let summary = summary(vec![
+      ("Opening", grouping(day(2023, Month::January, 1), day(2023, Month::January, 1))),
+      ("Year", grouping(day(2023, Month::January, 1), day(2023, Month::December, 31))),
+    ]);
+    assert_eq!(budget.expected("Opening", months(&summary.groupings[0].1), "food"), Some(Decimal::from(150)));
+    assert_eq!(budget.expected_total(&summary, "food"), Some(Decimal::from(1800)));
+    assert_eq!(budget.expected_total(&summary, "rent"), None);
+  }
+
+  #[test]
+  fn total_with_grouping_amount() {
+    let budget = Budget{
+      monthly: [("food".to_owned(), Decimal::from(150))].into(),
+      groupings: [("Jan".to_owned(), [("food".to_owned(), Decimal::from(100))].into())].into(),
+    };
+    let summary = summary(vec![
+      ("Opening", grouping(day(2023, Month::January, 1), day(2023, Month::January, 1))),
+      ("Jan", grouping(day(2023, Month::January, 1), day(2023, Month::January, 31))),
+      ("Feb", grouping(day(2023, Month::February, 1), day(2023, Month::February, 28))),
+    ]);
+    // January is budgeted by its grouping, February monthly
+    assert_eq!(budget.expected_total(&summary, "food"), Some(Decimal::from(250)));
+  }
+}
@@ -6,6 +6,11 @@ use serde::{Serialize};
 use rust_decimal::Decimal;
 
 use crate::types::*;
+use crate::budget::Budget;
+use crate::metrics::{
+  MetricValue,
+  evaluate_metrics,
+};
 
 // Here we should do two things:
 // - calculated sums for every relevant level
@@ -22,23 +27,121 @@ pub struct Transfer {
   // Other transfers in the same Transaction
   // (Their sum is asserted to be -1 * Transfer.amount)
   pub related_transfers: Vec<(String, Decimal)>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub generated_by: Option<String>,
+  // The comments of the transaction, by key
+  #[serde(skip)]
+  pub comments: Vec<(String, String)>,
+  // Where the transaction is written, None if generated
+  #[serde(skip)]
+  pub source: Option<Source>,
 }
 #[derive(Debug, Serialize, Clone)]
 pub struct SummedAccount {
   pub name: String,
+  // From the account's details, if given
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub number: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub currency: Option<String>,
+  #[serde(skip_serializing_if = "std::ops::Not::not")]
+  pub hidden: bool,
   pub sum: Decimal,
   // We use a set to order the transfers, otherwise they come in the order
   // they are read from their groupings and are chunked per grouping.
   pub transfers: BTreeSet<Transfer>,
 }
+// A level in the account hierarchy given by colon separated account names.
+// (An account can both have a balance of its own and sub-accounts.)
+#[derive(Debug, Serialize, Clone)]
+pub struct SummedNode {
+  // The full name, such as "home:electricity"
+  pub name: String,
+  pub sum: Decimal,
+  pub children: Vec<SummedNode>,
+}
+// An account in an account sum with the factor it is summed with, -1 if subtracted
+pub type FactoredAccount = (Decimal, SummedAccount);
 #[derive(Debug, Serialize)]
 pub struct SummedGrouping {
+  // The dates the grouping covers if given, never for the total
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub period: Option<Period>,
   pub account_types: Vec<(AccountType, Decimal, Vec<SummedAccount>)>,
-  pub account_sums: Vec<(String, Decimal, Vec<SummedAccount>)>,
+  pub account_sums: Vec<(String, Decimal, Vec<FactoredAccount>)>,
+  pub account_hierarchy: Vec<SummedNode>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub metrics: Vec<MetricValue>,
+}
+
+impl SummedAccount {
+  fn new(infos: &BTreeMap<String, AccountInfo>, account: &str, transfer: Transfer) -> Self {
+    let info = infos.get(account).cloned().unwrap_or_default();
+    SummedAccount{
+      name: account.to_owned(),
+      number: info.number,
+      description: info.description,
+      currency: info.currency,
+      hidden: info.hidden,
+      sum: Decimal::ZERO,
+      transfers: [transfer].into(),
+    }
+  }
+  /// The name prefixed with the account number, if any.
+  pub fn label(&self) -> String {
+    match self.number {
+      Some(number) => format!("{} {}", number, self.name),
+      None => self.name.clone(),
+    }
+  }
+}
+
+// Sum the resolved account_sums, skipping accounts without transfers
+fn sum_account_sums(
+  sums: &[(String, Vec<(String, Decimal)>)],
+  accounts: &BTreeMap<String, SummedAccount>,
+) -> Vec<(String, Decimal, Vec<FactoredAccount>)> {
+  sums.iter().map(|(sum_name, factors)| {
+    let mut sum = Decimal::ZERO;
+    let mut summed_accounts = Vec::new();
+    for (account, factor) in factors {
+      if let Some(acc) = accounts.get(account) {
+        sum += acc.sum * factor;
+        summed_accounts.push((*factor, acc.clone()));
+      }
+    }
+    (sum_name.to_owned(), sum, summed_accounts)
+  }).collect()
+}
+
+// Sum the accounts below the given prefix ("" for the top level) into nodes
+fn account_hierarchy(accounts: &BTreeMap<String, SummedAccount>, prefix: &str) -> Vec<SummedNode> {
+  let names = accounts.keys()
+    .filter_map(|name| name.strip_prefix(prefix))
+    .map(|rest| format!("{}{}", prefix, rest.split(':').next().unwrap()))
+    .collect::<BTreeSet<String>>()
+  ;
+  names.into_iter().map(|name| {
+    let children = account_hierarchy(accounts, &format!("{}:", name));
+    let own = accounts.get(&name).map(|acc| acc.sum).unwrap_or(Decimal::ZERO);
+    SummedNode{
+      sum: own + children.iter().map(|c| c.sum).sum::<Decimal>(),
+      name,
+      children,
+    }
+  }).collect()
 }
 #[derive(Debug, Serialize)]
 pub struct SummedBookkeeping {
   pub name: String,
+  // Passed through for comparing with the sums
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub budget: Option<Budget>,
+  // Passed through for the reports, the summed accounts also carry it
+  #[serde(skip)]
+  pub account_info: BTreeMap<String, AccountInfo>,
   pub total: SummedGrouping,
   #[serde(with = "tuple_vec_map")]
   pub groupings: Vec<(String, SummedGrouping)>,
@@ -64,6 +167,8 @@ pub fn calculate(data: RealBookkeeping) -> SummedBookkeeping {
     for transaction in &grouping.transactions {
       // Track the per-transaction sum, should be 0 error otherwise
       let mut sum = Decimal::ZERO;
+      let mut comments: Vec<(String, String)> = transaction.comments.clone().into_iter().collect();
+      comments.sort();
       // And save the data into relevant sum locations
       for (i, (account, amount)) in transaction.transfers.iter().enumerate() {
         sum += amount;
@@ -75,13 +180,16 @@ pub fn calculate(data: RealBookkeeping) -> SummedBookkeeping {
 
         // Per-account transfer aggregation (sums calculated after, while setting resulting_balance)
         let transfer = Transfer {
-          date: transaction.date.clone(),
+          date: transaction.date,
           name: transaction.name.clone(),
           amount: *amount,
           resulting_balance: Decimal::ZERO,
           unique_id: format!("{}[{}][{}]", grouping.name, transaction.index, i),
           // Includes self, but who cares
           related_transfers: transaction.transfers.clone(),
+          generated_by: transaction.generated_by.clone(),
+          comments: comments.clone(),
+          source: transaction.source.clone(),
         };
         // Global
         total_accounts.entry(account.to_owned())
@@ -90,11 +198,7 @@ pub fn calculate(data: RealBookkeeping) -> SummedBookkeeping {
               transfer.clone()
             ) { panic!("Identical transactions matching: {:?}", transaction) }
           })
-          .or_insert(SummedAccount{
-            name: account.to_owned(),
-            sum: Decimal::ZERO,
-            transfers: [transfer.clone()].into(),
-          })
+          .or_insert_with(|| SummedAccount::new(&data.account_info, account, transfer.clone()))
         ;
         // Local
         grouping_accounts.entry(account.to_owned())
@@ -103,11 +207,7 @@ pub fn calculate(data: RealBookkeeping) -> SummedBookkeeping {
               transfer.clone()
             ) { panic!("Identical transactions matching: {:?}", transaction) }
           })
-          .or_insert(SummedAccount{
-            name: account.to_owned(),
-            sum: Decimal::ZERO,
-            transfers: [transfer.clone()].into(),
-          })
+          .or_insert_with(|| SummedAccount::new(&data.account_info, account, transfer.clone()))
         ;
       }
       if sum != Decimal::ZERO {
@@ -130,18 +230,7 @@ pub fn calculate(data: RealBookkeeping) -> SummedBookkeeping {
     }).fold(BTreeMap::new(), |mut map, (k,v)| { assert!(map.insert(k,v).is_none()); map } );
 
     // After summing all transactions, use the account sums to sum account categories
-    let mut account_sums = Vec::new();
-    for (sum_name, accounts) in data.account_sums.iter() {
-      let mut sum = Decimal::ZERO;
-      let mut summed_accounts = Vec::new();
-      for account in accounts {
-        if let Some(acc) = grouping_accounts.get(account) {
-          sum += acc.sum;
-          summed_accounts.push(acc.clone());
-        }
-      }
-      account_sums.push((sum_name.to_owned(), sum, summed_accounts));
-    }
+    let account_sums = sum_account_sums(&data.account_sums, &grouping_accounts);
 
     // And the same for account types
     let mut account_types = Vec::new();
@@ -158,7 +247,10 @@ pub fn calculate(data: RealBookkeeping) -> SummedBookkeeping {
     }
 
     // Whereafter we can add the summed grouping
-    summed_periods.push((grouping.name, SummedGrouping{account_types, account_sums}));
+    let account_hierarchy = account_hierarchy(&grouping_accounts, "");
+    let mut summed = SummedGrouping{period: grouping.period, account_types, account_sums, account_hierarchy, metrics: Vec::new()};
+    summed.metrics = evaluate_metrics(&data.metrics, &summed);
+    summed_periods.push((grouping.name, summed));
   }
 
   // Finally do the same summing of account_sums and account_types as within
@@ -178,18 +270,7 @@ pub fn calculate(data: RealBookkeeping) -> SummedBookkeeping {
     (account, sums)
   }).fold(BTreeMap::new(), |mut map, (k,v)| { assert!(map.insert(k,v).is_none()); map } );
 
-  let mut account_sums = Vec::new();
-  for (sum_name, accounts) in data.account_sums.iter() {
-    let mut sum = Decimal::ZERO;
-    let mut summed_accounts = Vec::new();
-    for account in accounts {
-      if let Some(acc) = total_accounts.get(account) {
-        sum += acc.sum;
-        summed_accounts.push(acc.clone());
-      }
-    }
-    account_sums.push((sum_name.to_owned(), sum, summed_accounts));
-  }
+  let account_sums = sum_account_sums(&data.account_sums, &total_accounts);
 
   let mut account_types = Vec::new();
   for (type_name, accounts) in data.account_types.iter() {
@@ -205,12 +286,19 @@ pub fn calculate(data: RealBookkeeping) -> SummedBookkeeping {
   }
 
   // Whereafter we can add the summed grouping
+  let mut total = SummedGrouping{
+    period: None,
+    account_types,
+    account_sums,
+    account_hierarchy: account_hierarchy(&total_accounts, ""),
+    metrics: Vec::new(),
+  };
+  total.metrics = evaluate_metrics(&data.metrics, &total);
   SummedBookkeeping{
     name: data.name,
-    total: SummedGrouping{
-      account_types,
-      account_sums,
-    },
+    budget: data.budget,
+    account_info: data.account_info,
+    total,
     groupings: summed_periods,
   }
 }
@@ -0,0 +1,255 @@
+//! Changing and removing transactions where they are written.
+//!
+//! The transaction is found by its index among the list items of its file (or
+//! of the inlined list of its grouping) and checked to still be what was read.
+//! Its lines are then replaced by it rendered as by the fmt command, or
+//! removed, unless the bookkeeping would then fail to load. The rest of the
+//! file is left as it is.
+
+use std::path::PathBuf;
+use rust_decimal::Decimal;
+
+use crate::types::*;
+use crate::file_io::{
+  FileIO,
+  PendingFileIO,
+};
+use crate::fmt::{
+  indent,
+  is_comment_or_blank,
+};
+use crate::add::{
+  inlined_list,
+  render,
+};
+
+fn source_path(source: &Source) -> (PathBuf, usize) {
+  match source {
+    Source::Inlined{ index, .. } => (PathBuf::from("bookkeeping.yaml"), *index),
+    Source::Path{ path, index } => (path.clone(), *index),
+  }
+}
+
+/// The transaction as written at the source, with any VAT codes, references
+/// and such that realizing it changes.
+pub fn written_transaction(io: &mut impl FileIO, source: &Source) -> Result<Transaction, String> {
+  let (path, index) = source_path(source);
+  let raw = io.read_path(&path);
+  let invalid = |e: serde_yaml::Error| format!("Invalid format at {}: {}", path.display(), e);
+  let transaction = match source {
+    Source::Inlined{ grouping, .. } => {
+      let book: Bookkeeping = serde_yaml::from_str(&raw).map_err(invalid)?;
+      book.groupings.into_iter()
+        .find(|g| &g.name == grouping)
+        .and_then(|g| match g.transactions {
+          Transactions::Inlined(transactions) => transactions.into_iter().nth(index),
+          Transactions::Paths(_) => None,
+        })
+    },
+    Source::Path{ .. } => serde_yaml::from_str::<Vec<Transaction>>(&raw).map_err(invalid)?
+      .into_iter().nth(index),
+  };
+  transaction.ok_or_else(|| format!("Transaction {} not found in {}, reload and try again", index + 1, path.display()))
+}
+
+/// The lines of each list item at the indentation, without the blank lines
+/// and less indented comments after it.
+pub fn item_ranges(lines: &[&str], start: usize, end: usize, item_indent: usize) -> Vec<(usize, usize)> {
+  let starts: Vec<usize> = (start..end)
+    .filter(|i| indent(lines[*i]) == item_indent)
+    .filter(|i| { let line = lines[*i].trim_start(); line.starts_with("- ") || line == "-" })
+    .collect()
+  ;
+  starts.iter().enumerate().map(|(n, start)| {
+    let mut end = starts.get(n + 1).copied().unwrap_or(end);
+    while end > start + 1 && is_comment_or_blank(lines[end - 1]) && indent(lines[end - 1]) <= item_indent {
+      end -= 1;
+    }
+    (*start, end)
+  }).collect()
+}
+
+// Drop the VAT codes and references given by account that are left without
+// a transfer, as when an edit renames or removes a transfer
+fn without_stale(transaction: &Transaction) -> Transaction {
+  fn keep<T: Clone>(transfers: &[(String, Decimal)], values: &[(String, T)]) -> Vec<(String, T)> {
+    values.iter().enumerate()
+      .filter(|(i, (account, _))| {
+        let nth = values[..*i].iter().filter(|(a, _)| a == account).count();
+        nth < transfers.iter().filter(|(a, _)| a == account).count()
+      })
+      .map(|(_, value)| value.clone())
+      .collect()
+  }
+  Transaction{
+    vat: keep(&transaction.transfers, &transaction.vat),
+    references: keep(&transaction.transfers, &transaction.references),
+    ..transaction.clone()
+  }
+}
+
+/// Replace the transaction written at the source with the new one, or remove
+/// it if None, if the check passes when reading the files as if it was done.
+/// The old transaction is what was read from there, to make sure the file
+/// hasn't changed since. Returns the file changed.
+pub fn replace_transaction<T: FileIO>(
+  io: &mut T,
+  source: &Source,
+  old: &Transaction,
+  new: Option<&Transaction>,
+  check: impl FnOnce(&mut PendingFileIO<T>) -> Result<(), String>,
+) -> Result<PathBuf, String> {
+  let (path, index) = source_path(source);
+  let changed = || format!("Transaction {} in {} has changed since it was read, reload and try again", old.name, path.display());
+  let raw = io.read_path(&path);
+  let lines: Vec<&str> = raw.lines().collect();
+  let (start, end, item_indent) = match source {
+    Source::Inlined{ grouping, .. } => inlined_list(&lines, grouping)?,
+    Source::Path{ .. } => {
+      let item_indent = lines.iter().find(|l| !is_comment_or_blank(l)).map(|l| indent(l)).unwrap_or(0);
+      (0, lines.len(), item_indent)
+    },
+  };
+  let items = item_ranges(&lines, start, end, item_indent);
+  let (item_start, item_end) = *items.get(index).ok_or_else(changed)?;
+  let block: Vec<&str> = lines[item_start..item_end].iter()
+    .map(|l| if indent(l) >= item_indent { &l[item_indent..] } else { l.trim_start() })
+    .collect()
+  ;
+  let written: Vec<Transaction> = serde_yaml::from_str(&block.join("\n")).map_err(|_| changed())?;
+  if written != [Transaction{ source: None, ..old.clone() }] { return Err(changed()); }
+
+  let pad = " ".repeat(item_indent);
+  let mut out: Vec<String> = Vec::new();
+  let rest = match new {
+    Some(new) => {
+      out.extend(lines[..item_start].iter().map(|l| l.to_string()));
+      out.extend(render(&without_stale(new)).lines().map(|l| format!("{}{}", pad, l)));
+      item_end
+    },
+    None if items.len() == 1 => match source {
+      Source::Inlined{ grouping, .. } => {
+        return Err(format!("{} is the only transaction of grouping {}, remove it by hand", old.name, grouping));
+      },
+      // Keep the file a valid, empty list
+      Source::Path{ .. } => {
+        out.extend(lines[..item_start].iter().map(|l| l.to_string()));
+        out.push(format!("{}[]", pad));
+        item_end
+      },
+    },
+    None => {
+      // Take the blank lines separating it from the others with it
+      let mut before = item_start;
+      let mut after = item_end;
+      if index + 1 == items.len() {
+        while before > items[index - 1].1 && lines[before - 1].trim().is_empty() { before -= 1; }
+      } else {
+        while after < lines.len() && lines[after].trim().is_empty() { after += 1; }
+      }
+      out.extend(lines[..before].iter().map(|l| l.to_string()));
+      after
+    },
+  };
+  out.extend(lines[rest..].iter().map(|l| l.to_string()));
+  let mut pending = PendingFileIO{ inner: io, path, contents: out.join("\n") + "\n" };
+  check(&mut pending).map_err(|e| format!("Not changed, the bookkeeping would be invalid: {}", e))?;
+  let PendingFileIO{ inner, path, contents } = pending;
+  inner.write_path(&path, &contents)?;
+  Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::file_io::FakeFileIO;
+  use crate::{
+    calculate,
+    catch_panic,
+    load,
+  };
+
+  const BOOK: &str = "\
+version: 1
+name: test
+accounts:
+  asset: [money, prepaid]
+  creditor: [vat_out_25, vat_out_12, vat_out_6, vat_in, vat_reverse]
+  expense: [food, software]
+vat:
+  output_25: vat_out_25
+  output_12: vat_out_12
+  output_6: vat_out_6
+  input: vat_in
+  reverse_charge: vat_reverse
+prepaid_account: prepaid
+account_sums: {}
+groupings:
+- name: Q1
+  period: {start: 2023-01-01, end: 2023-03-31}
+  transactions: !Paths [q1.yaml]
+";
+
+  const Q1: &str = "\
+- name: lunch
+  date: 2023-01-10
+  transfers:
+    food:   112
+    money: -112
+  vat:
+    food: vat12
+- name: licence
+  date: 2023-01-20
+  accrue: 2023-01..2023-03
+  transfers:
+    software:  125
+    money:    -125
+";
+
+  fn io() -> FakeFileIO {
+    FakeFileIO{ files: [
+      (PathBuf::from("bookkeeping.yaml"), BOOK.to_owned()),
+      (PathBuf::from("q1.yaml"), Q1.to_owned()),
+    ].into() }
+  }
+
+  fn source(index: usize) -> Source {
+    Source::Path{ path: PathBuf::from("q1.yaml"), index }
+  }
+
+  fn replace(io: &mut FakeFileIO, index: usize, edit: Option<&dyn Fn(&mut Transaction)>) -> Result<PathBuf, String> {
+    let old = written_transaction(io, &source(index)).unwrap();
+    let new = edit.map(|edit| { let mut new = old.clone(); edit(&mut new); new });
+    replace_transaction(io, &source(index), &old, new.as_ref(), |io| catch_panic(|| { calculate(load(io)); }))
+  }
+
+  #[test]
+  fn renamed_transfer_drops_its_code() {
+    let mut io = io();
+    replace(&mut io, 0, Some(&|t| t.transfers[0].0 = "software".to_owned())).unwrap();
+    let edited = written_transaction(&mut io, &source(0)).unwrap();
+    assert_eq!(edited.transfers[0].0, "software");
+    assert!(edited.vat.is_empty());
+    // Still loads, and the other transaction is as it was
+    calculate(load(&mut io));
+    assert!(io.files[&PathBuf::from("q1.yaml")].ends_with(&Q1[Q1.find("- name: licence").unwrap()..]));
+  }
+
+  #[test]
+  fn invalid_edit_is_not_written() {
+    let mut io = io();
+    // Accruing needs an expense to spread
+    let e = replace(&mut io, 1, Some(&|t| t.transfers[0].0 = "prepaid".to_owned())).unwrap_err();
+    assert!(e.starts_with("Not changed, the bookkeeping would be invalid: "), "{}", e);
+    assert_eq!(io.files[&PathBuf::from("q1.yaml")], Q1);
+  }
+
+  #[test]
+  fn delete() {
+    let mut io = io();
+    replace(&mut io, 0, None).unwrap();
+    assert_eq!(io.files[&PathBuf::from("q1.yaml")], Q1[Q1.find("- name: licence").unwrap()..]);
+    assert!(replace(&mut io, 0, Some(&|t| t.name = "changed".to_owned())).is_ok());
+    assert!(written_transaction(&mut io, &source(1)).is_err());
+  }
+}
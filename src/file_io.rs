@@ -1,10 +1,14 @@
 //! Only exists so that I can hand in test versions of FileIO to some tests.
-//! Aside from that we only use std::fs::read_to_string().
+//! Aside from that we only use std::fs::read_to_string() and std::fs::write().
 
-use std::path::Path;
+use std::path::{
+  Path,
+  PathBuf,
+};
 
 pub trait FileIO {
   fn read_path(&mut self, path: &Path) -> String;
+  fn write_path(&mut self, path: &Path, contents: &str) -> Result<(), String>;
 }
 
 pub struct StdFileIO {
@@ -12,39 +16,44 @@ pub struct StdFileIO {
 impl FileIO for StdFileIO {
   fn read_path(&mut self, path: &Path) -> String {
     std::fs::read_to_string(path)
-      .expect(&format!("File not found: {}", path.display()))
+      .unwrap_or_else(|_| panic!("File not found: {}", path.display()))
+  }
+  fn write_path(&mut self, path: &Path, contents: &str) -> Result<(), String> {
+    std::fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
   }
 }
 
-#[cfg(test)]
-pub struct FakeFileIO {
-  fake_fs: std::collections::HashMap<&'static Path, &'static str>,
+/// Reads as if the contents were written to the path, without writing them,
+/// so the result of a change can be checked before it is made.
+pub struct PendingFileIO<'a, T> {
+  pub inner: &'a mut T,
+  pub path: PathBuf,
+  pub contents: String,
 }
-#[cfg(test)]
-impl FakeFileIO {
-  pub fn new() -> Self {
-    let mut out = std::collections::HashMap::new();
-    out.insert(Path::new("grouping.yaml"), "---
-  name: file-grouping
-  transactions:
-    - name: file-transaction
-      date: 2023-01-30
-      transfers:
-        debts: -300
-        money: 300
-  "
-    );
-    Self{
-      fake_fs: out,
-    }
+impl<T: FileIO> FileIO for PendingFileIO<'_, T> {
+  fn read_path(&mut self, path: &Path) -> String {
+    if path == self.path { self.contents.clone() } else { self.inner.read_path(path) }
+  }
+  fn write_path(&mut self, path: &Path, contents: &str) -> Result<(), String> {
+    self.inner.write_path(path, contents)
   }
 }
+
+// Files kept in memory by path, for tests that write
+#[cfg(test)]
+pub struct FakeFileIO {
+  pub files: std::collections::HashMap<PathBuf, String>,
+}
 #[cfg(test)]
 impl FileIO for FakeFileIO {
   fn read_path(&mut self, path: &Path) -> String {
-    self.fake_fs.get(path)
-      .expect(&format!("File not found: {}", path.display()))
-      .to_string()
+    self.files.get(path)
+      .unwrap_or_else(|| panic!("File not found: {}", path.display()))
+      .clone()
+  }
+  fn write_path(&mut self, path: &Path, contents: &str) -> Result<(), String> {
+    self.files.insert(path.to_owned(), contents.to_owned());
+    Ok(())
   }
 }
 #[cfg(test)]
@@ -54,4 +63,7 @@ impl FileIO for DummyFileIO {
   fn read_path(&mut self, _path: &Path) -> String {
     unimplemented!()
   }
+  fn write_path(&mut self, _path: &Path, _contents: &str) -> Result<(), String> {
+    unimplemented!()
+  }
 }
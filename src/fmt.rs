@@ -0,0 +1,371 @@
+//! Formatting transaction files into a canonical form, for clean diffs.
+//!
+//! This works on the lines of the file rather than through serde_yaml, so
+//! comments are kept. Each transaction is parsed to get its date and amounts,
+//! but only its `date:` line and `transfers:` block are rewritten:
+//! - transactions are sorted by date, keeping the order within a date
+//! - dates are written as plain YYYY-MM-DD
+//! - transfers are ordered debits (positive) before credits (negative)
+//! - amounts get at least two decimals and are aligned
+//!
+//! Comments before a transaction move with it, comments before the first
+//! transaction stay at the top of the file. In bookkeeping.yaml the inlined
+//! transactions of each grouping are formatted the same way, leaving the rest
+//! of the file as it is.
+
+use std::path::PathBuf;
+use rust_decimal::Decimal;
+
+use crate::types::*;
+use crate::add::inlined_list;
+
+// Split off a trailing comment, which is only looked for after whitespace
+fn split_comment(line: &str) -> (&str, &str) {
+  match line.find(" #") {
+    Some(i) => (line[..i].trim_end(), &line[i..]),
+    None => (line.trim_end(), ""),
+  }
+}
+
+pub fn indent(line: &str) -> usize {
+  line.len() - line.trim_start().len()
+}
+
+pub fn is_comment_or_blank(line: &str) -> bool {
+  let trimmed = line.trim();
+  trimmed.is_empty() || trimmed.starts_with('#')
+}
+
+// Split a `key: value` line into its key as written and the value, handling
+// quoted keys containing colons
+pub fn split_key(line: &str) -> Option<(&str, &str)> {
+  let trimmed = line.trim_start();
+  let key_end = match trimmed.chars().next()? {
+    q @ ('"' | '\'') => trimmed[1..].find(q)? + 2,
+    _ => trimmed.find(':')?,
+  };
+  let rest = trimmed[key_end..].strip_prefix(':')?;
+  if !(rest.is_empty() || rest.starts_with(' ')) { return None; }
+  Some((&trimmed[..key_end], rest.trim_start()))
+}
+
+fn format_amount(amount: Decimal) -> String {
+  let amount = amount.normalize();
+  // Never round away precision that was given
+  if amount.scale() <= 2 { format!("{:.2}", amount) } else { amount.to_string() }
+}
+
+// Rewrite the lines of the transfers block, if it is of the simple form with
+// one `account: amount` per line. Returns None to leave it as is.
+fn format_transfers(lines: &[&str], transfers: &[(String, Decimal)]) -> Option<Vec<String>> {
+  let entry_indent = lines.iter().find(|l| !is_comment_or_blank(l)).map(|l| indent(l))?;
+  // Each entry with the comment lines before it
+  let mut entries: Vec<(Vec<&str>, &str, &str)> = Vec::new();
+  let mut comments = Vec::new();
+  for line in lines {
+    if is_comment_or_blank(line) { comments.push(*line); continue; }
+    if indent(line) != entry_indent { return None; }
+    let (content, comment) = split_comment(line);
+    let (key, _) = split_key(content)?;
+    entries.push((std::mem::take(&mut comments), key, comment));
+  }
+  if entries.len() != transfers.len() { return None; }
+  let mut entries: Vec<_> = entries.into_iter().zip(transfers).collect();
+  // Codes are paired with transfers on the same account in order, so only
+  // reorder if every account is only transferred to once
+  let unique = transfers.iter().enumerate()
+    .all(|(i, (a, _))| transfers[..i].iter().all(|(b, _)| a != b))
+  ;
+  if unique {
+    entries.sort_by_key(|(_, (_, amount))| amount.is_sign_negative());
+  }
+  let key_width = entries.iter().map(|((_, key, _), _)| key.len()).max().unwrap_or(0);
+  let amount_width = entries.iter().map(|(_, (_, amount))| format_amount(*amount).len()).max().unwrap_or(0);
+  let pad = " ".repeat(entry_indent);
+  let mut out = Vec::new();
+  for ((comments, key, comment), (_, amount)) in entries {
+    out.extend(comments.into_iter().map(|c| c.to_owned()));
+    out.push(format!("{}{}:{} {:>w$}{}",
+      pad, key, " ".repeat(key_width - key.len()), format_amount(*amount), comment,
+      w = amount_width,
+    ));
+  }
+  out.extend(comments.into_iter().map(|c| c.to_owned()));
+  Some(out)
+}
+
+// Format the lines of one transaction (the first starting with "- ")
+fn format_transaction(lines: &[&str], transaction: &Transaction) -> Vec<String> {
+  let key_indent = indent(&lines[0][1..]) + 1;
+  let mut out = Vec::new();
+  let mut i = 0;
+  while i < lines.len() {
+    // Look at the first line as if the "- " was indentation
+    let (prefix, line) = if i == 0 {
+      (&lines[0][..key_indent], format!("{:w$}{}", "", &lines[0][key_indent..], w = key_indent))
+    } else {
+      ("", lines[i].to_owned())
+    };
+    let at_key = !is_comment_or_blank(&line) && indent(&line) == key_indent;
+    let (content, comment) = split_comment(&line);
+    match split_key(content).filter(|_| at_key) {
+      Some(("date", _)) => {
+        out.push(format!("{:w$}date: {}{}", prefix, transaction.date, comment, w = key_indent));
+        i += 1;
+      },
+      Some(("transfers", "")) => {
+        out.push(format!("{:w$}{}", prefix, line.trim_start(), w = key_indent));
+        let start = i + 1;
+        let mut end = start;
+        while end < lines.len() && (is_comment_or_blank(lines[end]) || indent(lines[end]) > key_indent) {
+          end += 1;
+        }
+        // Comments after the block belong to whatever follows it
+        while end > start && is_comment_or_blank(lines[end - 1]) { end -= 1; }
+        match format_transfers(&lines[start..end], &transaction.transfers) {
+          Some(block) => out.extend(block),
+          None => out.extend(lines[start..end].iter().map(|l| l.to_string())),
+        }
+        i = end;
+      },
+      _ => {
+        out.push(lines[i].to_owned());
+        i += 1;
+      },
+    }
+  }
+  out
+}
+
+/// Format a file containing a list of transactions.
+pub fn format_transactions(raw: &str) -> Result<String, String> {
+  let lines: Vec<&str> = raw.lines().collect();
+  let starts: Vec<usize> = lines.iter().enumerate()
+    .filter(|(_, l)| l.starts_with("- ") || **l == "-")
+    .map(|(i, _)| i)
+    .collect()
+  ;
+  let first = match starts.first() {
+    Some(first) => *first,
+    None => return Ok(raw.to_owned()),
+  };
+  // Where each transaction starts and ends, and where the comments after it
+  // (at column 0, so belonging to the next transaction) end
+  let mut items = Vec::new();
+  let mut separated = false;
+  for (n, start) in starts.iter().enumerate() {
+    let mut comments_end = starts.get(n + 1).copied().unwrap_or(lines.len());
+    while comments_end > start + 1 && lines[comments_end - 1].trim().is_empty() {
+      comments_end -= 1;
+    }
+    let mut end = comments_end;
+    while end > start + 1 && is_comment_or_blank(lines[end - 1]) && indent(lines[end - 1]) == 0 {
+      end -= 1;
+    }
+    // Blank lines between transactions, also before the comments of the next
+    if let Some(next) = starts.get(n + 1) {
+      separated |= lines[end..*next].iter().any(|l| l.trim().is_empty());
+    }
+    items.push((*start, end, comments_end));
+  }
+  let mut formatted = Vec::new();
+  for (n, (start, end, _)) in items.iter().enumerate() {
+    let mut out: Vec<String> = Vec::new();
+    if n > 0 {
+      let (_, prev_end, prev_comments_end) = items[n - 1];
+      out.extend(lines[prev_end..prev_comments_end].iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.to_string())
+      );
+    }
+    let chunk = lines[*start..*end].join("\n");
+    let transaction = serde_yaml::from_str::<Vec<Transaction>>(&chunk)
+      .map_err(|e| format!("Invalid transaction at line {}: {}", start + 1, e))?
+      .pop()
+      .ok_or_else(|| format!("Invalid transaction at line {}", start + 1))?
+    ;
+    out.extend(format_transaction(&lines[*start..*end], &transaction));
+    formatted.push((transaction.date, out));
+  }
+  formatted.sort_by_key(|(date, _)| *date);
+
+  let mut out: Vec<String> = lines[..first].iter().map(|l| l.to_string()).collect();
+  for (n, (_, item)) in formatted.into_iter().enumerate() {
+    if separated && n > 0 { out.push(String::new()); }
+    out.extend(item);
+  }
+  // Comments after the last transaction stay at the end
+  let (_, last_end, _) = items[items.len() - 1];
+  let mut trailing = &lines[last_end..];
+  while trailing.last().is_some_and(|l| l.trim().is_empty()) {
+    trailing = &trailing[..trailing.len() - 1];
+  }
+  out.extend(trailing.iter().map(|l| l.to_string()));
+  Ok(out.join("\n") + "\n")
+}
+
+/// Format the inlined transactions of the groupings in bookkeeping.yaml.
+pub fn format_bookkeeping(raw: &str) -> Result<String, String> {
+  let book: Bookkeeping = serde_yaml::from_str(raw).map_err(|e| format!("Invalid format: {}", e))?;
+  let mut lines: Vec<String> = raw.lines().map(|l| l.to_owned()).collect();
+  for grouping in &book.groupings {
+    if !matches!(grouping.transactions, Transactions::Inlined(_)) { continue; }
+    let borrowed: Vec<&str> = lines.iter().map(|l| l.as_str()).collect();
+    // Empty lists and lists on one line are left as they are
+    let (start, end, item_indent) = match inlined_list(&borrowed, &grouping.name) {
+      Ok(list) => list,
+      Err(_) => continue,
+    };
+    let block: Vec<&str> = borrowed[start..end].iter()
+      .map(|l| if indent(l) >= item_indent { &l[item_indent..] } else { l.trim_start() })
+      .collect()
+    ;
+    let formatted = format_transactions(&block.join("\n"))
+      .map_err(|e| format!("In the transactions of grouping {}: {}", grouping.name, e))?
+    ;
+    let pad = " ".repeat(item_indent);
+    let formatted: Vec<String> = formatted.lines()
+      .map(|l| if l.is_empty() { String::new() } else { format!("{}{}", pad, l) })
+      .collect()
+    ;
+    lines.splice(start..end, formatted);
+  }
+  Ok(lines.join("\n") + "\n")
+}
+
+/// The transaction files the groupings of the bookkeeping include.
+pub fn transaction_files(book: &Bookkeeping) -> Vec<PathBuf> {
+  book.groupings.iter()
+    .flat_map(|g| match &g.transactions {
+      Transactions::Paths(paths) => paths.clone(),
+      Transactions::Inlined(_) => Vec::new(),
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const RAW: &str = "\
+# Kept at the top
+- name: rent
+  date: '2023-02-01'
+  transfers:
+    money: -5000
+    # The flat
+    rent: 5_000
+  receipt: rent.pdf # a comment field
+
+# Moves with lunch
+- name: lunch
+  date: 2023-01-05
+  transfers:
+    money: -100.5
+    food: 100.5
+# Kept at the end
+";
+
+  const FORMATTED: &str = "\
+# Kept at the top
+# Moves with lunch
+- name: lunch
+  date: 2023-01-05
+  transfers:
+    food:   100.50
+    money: -100.50
+
+- name: rent
+  date: 2023-02-01
+  transfers:
+    # The flat
+    rent:   5000.00
+    money: -5000.00
+  receipt: rent.pdf # a comment field
+# Kept at the end
+";
+
+  #[test]
+  fn comments_kept() {
+    assert_eq!(format_transactions(RAW).unwrap(), FORMATTED);
+  }
+
+  #[test]
+  fn idempotent() {
+    assert_eq!(format_transactions(FORMATTED).unwrap(), FORMATTED);
+    // Transfers to the same account twice keep their order, as VAT codes
+    // are paired with them in order
+    let repeated = "\
+- name: sale
+  date: 2023-01-05
+  transfers:
+    sales: -100
+    money:  212
+    sales: -112
+  vat:
+    sales: vat25
+    sales: vat12
+";
+    let once = format_transactions(repeated).unwrap();
+    assert_eq!(format_transactions(&once).unwrap(), once);
+    assert!(once.contains("    sales: -100.00\n    money:  212.00\n    sales: -112.00\n"), "{}", once);
+  }
+
+  #[test]
+  fn inlined() {
+    let book = "\
+version: 1
+name: test
+accounts:
+  asset: [money]
+  expense: [food]
+account_sums: {}
+groupings:
+# Before the grouping
+- name: January
+  transactions: !Inlined
+  - name: b
+    date: 2023-01-20
+    transfers:
+      money: -2
+      food: 2
+  # Moves with a
+  - name: a
+    date: 2023-01-10
+    transfers:
+      money: -1
+      food: 1
+  # After the list
+- name: February
+  transactions: !Inlined []
+";
+    let formatted = format_bookkeeping(book).unwrap();
+    assert_eq!(formatted, "\
+version: 1
+name: test
+accounts:
+  asset: [money]
+  expense: [food]
+account_sums: {}
+groupings:
+# Before the grouping
+- name: January
+  transactions: !Inlined
+  # Moves with a
+  - name: a
+    date: 2023-01-10
+    transfers:
+      food:   1.00
+      money: -1.00
+  - name: b
+    date: 2023-01-20
+    transfers:
+      food:   2.00
+      money: -2.00
+  # After the list
+- name: February
+  transactions: !Inlined []
+");
+    assert_eq!(format_bookkeeping(&formatted).unwrap(), formatted);
+  }
+}
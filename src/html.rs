@@ -0,0 +1,339 @@
+//! HTML pages of the calculated bookkeeping, with what the terminal shows.
+//!
+//! There is an overview of the totals, a page per grouping, a page per account
+//! (within a grouping or over all of them) listing its transfers with their
+//! running balance, and a balance sheet and income statement. How pages link
+//! to each other and to files named in comments is up to the caller, so they
+//! can be served as well as written to files. Styles are inlined and nothing
+//! is loaded from elsewhere.
+
+use std::fmt::Write;
+use rust_decimal::Decimal;
+
+use crate::types::AccountType;
+use crate::calculate::*;
+use crate::budget::months;
+use crate::invoice::escape_html;
+
+/// A page, to link to.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Page<'a> {
+  Overview,
+  Grouping(&'a str),
+  /// The account's transfers in the grouping, or in all if None
+  Account(Option<&'a str>, &'a str),
+  BalanceSheet,
+  IncomeStatement,
+}
+
+/// How the pages link to each other and to files named in comments.
+pub trait Links {
+  fn page(&self, page: &Page) -> String;
+  /// The link to the file at the path (relative to the bookkeeping), if any
+  fn file(&self, path: &str) -> Option<String>;
+}
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 70em; margin: 1em auto; padding: 0 1em; } \
+  table { border-collapse: collapse; margin-bottom: 1em; } th, td { padding: 0.2em 0.6em; text-align: left; } \
+  .num { text-align: right; font-variant-numeric: tabular-nums; } thead { border-bottom: 1px solid black; } \
+  tbody tr:nth-child(even) { background: #f3f3f3; } nav a { margin-right: 1em; } .muted { color: #777; } \
+  ul.tree { list-style: none; padding-left: 1.2em; }";
+
+fn link(href: &str, text: &str) -> String {
+  format!("<a href=\"{}\">{}</a>", escape_html(href), escape_html(text))
+}
+
+/// The page with the given title and body, with the extra content (such as
+/// a script) in its head.
+pub fn document(title: &str, head: &str, body: &str) -> String {
+  let mut out = String::new();
+  writeln!(out, "<!DOCTYPE html>").unwrap();
+  writeln!(out, "<html><head><meta charset=\"utf-8\"><title>{}</title>", escape_html(title)).unwrap();
+  writeln!(out, "<style>{}</style>{}", STYLE, head).unwrap();
+  writeln!(out, "</head><body>").unwrap();
+  out.push_str(body);
+  writeln!(out, "</body></html>").unwrap();
+  out
+}
+
+fn grouping<'a>(summary: &'a SummedBookkeeping, name: Option<&str>) -> Option<&'a SummedGrouping> {
+  match name {
+    None => Some(&summary.total),
+    Some(name) => summary.groupings.iter().find(|(n, _)| n == name).map(|(_, g)| g),
+  }
+}
+
+fn nav(summary: &SummedBookkeeping, links: &dyn Links) -> String {
+  let mut out = format!("<nav>{}", link(&links.page(&Page::Overview), "Totals"));
+  for (name, _) in &summary.groupings {
+    out.push_str(&link(&links.page(&Page::Grouping(name)), name));
+  }
+  out.push_str(&link(&links.page(&Page::BalanceSheet), "Balance sheet"));
+  out.push_str(&link(&links.page(&Page::IncomeStatement), "Income statement"));
+  out.push_str("</nav>\n");
+  out
+}
+
+fn budget_cell(budget: Option<Decimal>, shown: bool) -> String {
+  match (shown, budget) {
+    (false, _) => String::new(),
+    (true, Some(b)) => format!("<td class=\"num\">{}</td>", b),
+    (true, None) => "<td></td>".to_owned(),
+  }
+}
+
+fn hierarchy(out: &mut String, gs: &SummedGrouping, nodes: &[SummedNode], page: &dyn Fn(&str) -> String) {
+  out.push_str("<ul class=\"tree\">\n");
+  for node in nodes {
+    let account = gs.account_types.iter()
+      .flat_map(|(_, _, accounts)| accounts)
+      .find(|a| a.name == node.name)
+    ;
+    if node.children.is_empty() && account.is_some_and(|a| a.hidden) { continue; }
+    let name = match account {
+      Some(a) if !a.hidden => link(&page(&a.name), &a.label()),
+      _ => escape_html(&node.name),
+    };
+    writeln!(out, "<li>{}: {}", name, node.sum).unwrap();
+    if !node.children.is_empty() {
+      hierarchy(out, gs, &node.children, page);
+    }
+    out.push_str("</li>\n");
+  }
+  out.push_str("</ul>\n");
+}
+
+// The account types, sums, hierarchy and metrics of the grouping or the total
+fn grouping_body(
+  summary: &SummedBookkeeping,
+  name: Option<&str>,
+  links: &dyn Links,
+) -> Option<String> {
+  let gs = grouping(summary, name)?;
+  let budget = |key: &str| summary.budget.as_ref().and_then(|b| match name {
+    None => b.expected_total(summary, key),
+    Some(name) => b.expected(name, months(gs), key),
+  });
+  let has_budget = summary.budget.is_some();
+  let page = |account: &str| links.page(&Page::Account(name, account));
+  let mut out = nav(summary, links);
+  writeln!(out, "<h1>{}: {}</h1>", escape_html(&summary.name), escape_html(name.unwrap_or("Totals"))).unwrap();
+
+  writeln!(out, "<h2>Account types</h2>").unwrap();
+  for (t, sum, accounts) in &gs.account_types {
+    let accounts: Vec<&SummedAccount> = accounts.iter().filter(|a| !a.hidden).collect();
+    if accounts.is_empty() { continue; }
+    writeln!(out, "<table><thead><tr><th>{:?}</th><th class=\"num\">{}</th>{}</tr></thead><tbody>",
+      t, sum, if has_budget { "<th class=\"num\">Budget</th>" } else { "" },
+    ).unwrap();
+    for account in accounts {
+      writeln!(out, "<tr><td>{}</td><td class=\"num\">{}</td>{}</tr>",
+        link(&page(&account.name), &account.label()), account.sum, budget_cell(budget(&account.name), has_budget),
+      ).unwrap();
+    }
+    writeln!(out, "</tbody></table>").unwrap();
+  }
+
+  if !gs.account_sums.is_empty() {
+    writeln!(out, "<h2>Account sums</h2>").unwrap();
+    for (sum_name, sum, accounts) in &gs.account_sums {
+      writeln!(out, "<table><thead><tr><th>{}</th><th class=\"num\">{}</th>{}</tr></thead><tbody>",
+        escape_html(sum_name), sum, budget_cell(budget(sum_name), has_budget),
+      ).unwrap();
+      for (factor, account) in accounts.iter().filter(|(_, a)| !a.hidden) {
+        let label = if *factor == Decimal::ONE { account.label() } else { format!("{} × {}", factor, account.label()) };
+        writeln!(out, "<tr><td>{}</td><td class=\"num\">{}</td>{}</tr>",
+          link(&page(&account.name), &label), account.sum, budget_cell(budget(&account.name), has_budget),
+        ).unwrap();
+      }
+      writeln!(out, "</tbody></table>").unwrap();
+    }
+  }
+
+  writeln!(out, "<h2>Account hierarchy</h2>").unwrap();
+  hierarchy(&mut out, gs, &gs.account_hierarchy, &page);
+
+  if !gs.metrics.is_empty() {
+    writeln!(out, "<h2>Metrics</h2>").unwrap();
+    writeln!(out, "<table><tbody>").unwrap();
+    for metric in &gs.metrics {
+      writeln!(out, "<tr><td>{}</td><td class=\"num\">{}</td></tr>", escape_html(&metric.name), metric).unwrap();
+    }
+    writeln!(out, "</tbody></table>").unwrap();
+  }
+  Some(out)
+}
+
+// The transfers of the account in the grouping or the total, with the other
+// transfers of their transactions
+fn account_body(
+  summary: &SummedBookkeeping,
+  name: Option<&str>,
+  account: &str,
+  links: &dyn Links,
+) -> Option<String> {
+  let gs = grouping(summary, name)?;
+  let account = gs.account_types.iter()
+    .flat_map(|(_, _, accounts)| accounts)
+    .find(|a| a.name == account)?
+  ;
+  let mut out = nav(summary, links);
+  writeln!(out, "<h1>{}</h1>", escape_html(&account.label())).unwrap();
+  let within = match name {
+    Some(name) => format!("{} ({})", link(&links.page(&Page::Grouping(name)), name), link(&links.page(&Page::Account(None, &account.name)), "all groupings")),
+    None => "all groupings".to_owned(),
+  };
+  writeln!(out, "<p>Sum in {}: {}</p>", within, account.sum).unwrap();
+  if let Some(description) = &account.description {
+    writeln!(out, "<p class=\"muted\">{}</p>", escape_html(description)).unwrap();
+  }
+  writeln!(out, "<table><thead><tr><th>Date</th><th>Transaction</th><th class=\"num\">Amount</th>\
+    <th class=\"num\">Balance</th><th>Other transfers</th><th>Comments</th></tr></thead><tbody>").unwrap();
+  for transfer in &account.transfers {
+    let others: Vec<String> = transfer.related_transfers.iter()
+      .filter(|(a, amount)| a != &account.name || *amount != transfer.amount)
+      .map(|(a, amount)| format!("{} {}", link(&links.page(&Page::Account(name, a)), a), amount))
+      .collect()
+    ;
+    let generated = match &transfer.generated_by {
+      Some(by) => format!(" <span class=\"muted\">[{}]</span>", escape_html(by)),
+      None => String::new(),
+    };
+    let comments: Vec<String> = transfer.comments.iter()
+      .map(|(key, value)| match links.file(value) {
+        Some(href) => format!("{}: {}", escape_html(key), link(&href, value)),
+        None => format!("{}: {}", escape_html(key), escape_html(value)),
+      })
+      .collect()
+    ;
+    writeln!(out, "<tr><td>{}</td><td>{}{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td>{}</td><td>{}</td></tr>",
+      transfer.date, escape_html(&transfer.name), generated, transfer.amount, transfer.resulting_balance,
+      others.join(", "), comments.join(", "),
+    ).unwrap();
+  }
+  writeln!(out, "</tbody></table>").unwrap();
+  Some(out)
+}
+
+/// The accounts of the types in the grouping, in the order of the types.
+pub fn accounts_of<'a>(gs: &'a SummedGrouping, types: &[AccountType]) -> Vec<&'a SummedAccount> {
+  gs.account_types.iter()
+    .filter(|(t, _, _)| types.contains(t))
+    .flat_map(|(_, _, accounts)| accounts)
+    .collect()
+}
+
+fn account_sum(gs: &SummedGrouping, account: &str) -> Decimal {
+  gs.account_types.iter()
+    .flat_map(|(_, _, accounts)| accounts)
+    .find(|a| a.name == account)
+    .map(|a| a.sum)
+    .unwrap_or(Decimal::ZERO)
+}
+
+// A section of accounts with their sums times the factor and any extra
+// amounts, returning its total
+fn statement_section(
+  out: &mut String,
+  title: &str,
+  accounts: &[&SummedAccount],
+  factor: Decimal,
+  extra: &[(&str, Decimal)],
+  links: &dyn Links,
+) -> Decimal {
+  writeln!(out, "<tr><th colspan=\"2\">{}</th></tr>", title).unwrap();
+  let mut total = Decimal::ZERO;
+  for account in accounts {
+    total += account.sum * factor;
+    writeln!(out, "<tr><td>{}</td><td class=\"num\">{}</td></tr>",
+      link(&links.page(&Page::Account(None, &account.name)), &account.label()), account.sum * factor,
+    ).unwrap();
+  }
+  for (name, amount) in extra {
+    total += amount;
+    writeln!(out, "<tr><td>{}</td><td class=\"num\">{}</td></tr>", name, amount).unwrap();
+  }
+  writeln!(out, "<tr><th>Total {}</th><th class=\"num\">{}</th></tr>", title.to_lowercase(), total).unwrap();
+  total
+}
+
+// What is owned against what is owed, after all groupings. Liabilities and
+// equity are shown as positive, with the result of the income and expenses
+// as part of the equity.
+fn balance_sheet_body(summary: &SummedBookkeeping, links: &dyn Links) -> String {
+  let gs = &summary.total;
+  let mut out = nav(summary, links);
+  writeln!(out, "<h1>{}: Balance sheet</h1>", escape_html(&summary.name)).unwrap();
+  writeln!(out, "<table><tbody>").unwrap();
+  let assets = statement_section(&mut out, "Assets", &accounts_of(gs, &[AccountType::Asset, AccountType::Debtor]), Decimal::ONE, &[], links);
+  let liabilities = statement_section(&mut out, "Liabilities", &accounts_of(gs, &[AccountType::Creditor]), -Decimal::ONE, &[], links);
+  let result: Decimal = -accounts_of(gs, &[AccountType::Income, AccountType::Expense]).iter().map(|a| a.sum).sum::<Decimal>();
+  let equity = statement_section(&mut out, "Equity", &accounts_of(gs, &[AccountType::YearlyResult]), -Decimal::ONE, &[("Result", result)], links);
+  writeln!(out, "<tr><th>Total liabilities and equity</th><th class=\"num\">{}</th></tr>", liabilities + equity).unwrap();
+  writeln!(out, "</tbody></table>").unwrap();
+  if assets != liabilities + equity {
+    writeln!(out, "<p>The assets differ from the liabilities and equity by {}.</p>", assets - liabilities - equity).unwrap();
+  }
+  out
+}
+
+// Income and expenses per grouping and in total. Income is shown as positive.
+fn income_statement_body(summary: &SummedBookkeeping, links: &dyn Links) -> String {
+  let mut out = nav(summary, links);
+  writeln!(out, "<h1>{}: Income statement</h1>", escape_html(&summary.name)).unwrap();
+  let columns: Vec<(Option<&str>, &SummedGrouping)> = summary.groupings.iter()
+    .map(|(name, gs)| (Some(name.as_str()), gs))
+    .chain([(None, &summary.total)])
+    .collect()
+  ;
+  write!(out, "<table><thead><tr><th></th>").unwrap();
+  for (name, _) in &columns {
+    let header = match name {
+      Some(name) => link(&links.page(&Page::Grouping(name)), name),
+      None => "Total".to_owned(),
+    };
+    write!(out, "<th class=\"num\">{}</th>", header).unwrap();
+  }
+  writeln!(out, "</tr></thead><tbody>").unwrap();
+  let mut result = vec![Decimal::ZERO; columns.len()];
+  for (title, t, factor) in [("Income", AccountType::Income, -Decimal::ONE), ("Expenses", AccountType::Expense, Decimal::ONE)] {
+    writeln!(out, "<tr><th colspan=\"{}\">{}</th></tr>", columns.len() + 1, title).unwrap();
+    let mut totals = vec![Decimal::ZERO; columns.len()];
+    for account in accounts_of(&summary.total, &[t]) {
+      write!(out, "<tr><td>{}</td>", link(&links.page(&Page::Account(None, &account.name)), &account.label())).unwrap();
+      for (i, (_, gs)) in columns.iter().enumerate() {
+        let sum = account_sum(gs, &account.name) * factor;
+        totals[i] += sum;
+        write!(out, "<td class=\"num\">{}</td>", sum).unwrap();
+      }
+      writeln!(out, "</tr>").unwrap();
+    }
+    write!(out, "<tr><th>Total {}</th>", title.to_lowercase()).unwrap();
+    for (i, total) in totals.iter().enumerate() {
+      write!(out, "<th class=\"num\">{}</th>", total).unwrap();
+      result[i] -= total * factor;
+    }
+    writeln!(out, "</tr>").unwrap();
+  }
+  write!(out, "<tr><th>Result</th>").unwrap();
+  for sum in result {
+    write!(out, "<th class=\"num\">{}</th>", sum).unwrap();
+  }
+  writeln!(out, "</tr></tbody></table>").unwrap();
+  out
+}
+
+/// The title and body of the page, None if it doesn't exist.
+pub fn render_page(
+  summary: &SummedBookkeeping,
+  page: &Page,
+  links: &dyn Links,
+) -> Option<(String, String)> {
+  match page {
+    Page::Overview => Some((summary.name.clone(), grouping_body(summary, None, links)?)),
+    Page::Grouping(name) => Some((format!("{}: {}", summary.name, name), grouping_body(summary, Some(name), links)?)),
+    Page::Account(name, account) => Some((account.to_string(), account_body(summary, *name, account, links)?)),
+    Page::BalanceSheet => Some((format!("{}: Balance sheet", summary.name), balance_sheet_body(summary, links))),
+    Page::IncomeStatement => Some((format!("{}: Income statement", summary.name), income_statement_body(summary, links))),
+  }
+}
@@ -0,0 +1,80 @@
+//! Scaffolding a new bookkeeping from one of the built in templates.
+//!
+//! The templates are included into the binary. `{year}` in them is replaced
+//! with the current year, both in file contents and file paths.
+
+use std::path::{
+  Path,
+  PathBuf,
+};
+
+pub struct Template {
+  pub name: &'static str,
+  pub description: &'static str,
+  // Path relative to the created directory and contents
+  pub files: &'static [(&'static str, &'static str)],
+}
+
+pub const TEMPLATES: &[Template] = &[
+  Template{
+    name: "household",
+    description: "Household budget with monthly groupings and a budget file",
+    files: &[
+      ("bookkeeping.yaml", include_str!("../templates/household/bookkeeping.yaml")),
+      ("budget.yaml", include_str!("../templates/household/budget.yaml")),
+      ("transactions/{year}-01.yaml", include_str!("../templates/household/january.yaml")),
+    ],
+  },
+  Template{
+    name: "sole_trader",
+    description: "Swedish sole trader (enskild firma) on the BAS chart, with VAT and invoicing",
+    files: &[
+      ("bookkeeping.yaml", include_str!("../templates/sole_trader/bookkeeping.yaml")),
+      ("transactions/{year}-q1.yaml", include_str!("../templates/sole_trader/q1.yaml")),
+    ],
+  },
+  Template{
+    name: "association",
+    description: "Small Swedish association (ideell förening) with monthly groupings",
+    files: &[
+      ("bookkeeping.yaml", include_str!("../templates/association/bookkeeping.yaml")),
+      ("transactions/{year}-01.yaml", include_str!("../templates/association/january.yaml")),
+    ],
+  },
+];
+
+/// The list of templates, for usage messages.
+pub fn template_list() -> String {
+  TEMPLATES.iter()
+    .map(|t| format!("  {:<13} {}\n", t.name, t.description))
+    .collect()
+}
+
+/// Write the files of the named template into the directory, returning the
+/// paths written. Refuses to overwrite an existing bookkeeping.
+pub fn init(template: &str, dir: &Path, year: i32) -> Vec<PathBuf> {
+  let template = TEMPLATES.iter().find(|t| t.name == template)
+    .unwrap_or_else(|| panic!("Unknown template {}, available are:\n{}", template, template_list()))
+  ;
+  if dir.join("bookkeeping.yaml").exists() {
+    panic!("{} already contains a bookkeeping.yaml, not overwriting it.", dir.display());
+  }
+  let year = year.to_string();
+  let mut written = Vec::new();
+  for (path, contents) in template.files {
+    let path = dir.join(path.replace("{year}", &year));
+    if path.exists() {
+      panic!("{} already exists, not overwriting it.", path.display());
+    }
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)
+        .unwrap_or_else(|e| panic!("Failed to create {}: {}", parent.display(), e))
+      ;
+    }
+    std::fs::write(&path, contents.replace("{year}", &year))
+      .unwrap_or_else(|e| panic!("Failed to write {}: {}", path.display(), e))
+    ;
+    written.push(path);
+  }
+  written
+}
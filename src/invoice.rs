@@ -0,0 +1,306 @@
+//! Invoices, generating both the receivable transaction and the document.
+//!
+//! Invoices are declared in the grouping they are booked in. Each generates a
+//! transaction from the income accounts (with VAT codes, so the VAT is split
+//! out as for any other transaction) to the debtor account, with a reference
+//! opening an item that the payment can settle.
+
+use std::fmt::Write;
+use serde::{
+  Serialize,
+  Deserialize,
+};
+use schemars::JsonSchema;
+use rust_decimal::Decimal;
+use time::Date;
+
+use crate::types::*;
+use crate::vat::VatCode;
+use crate::open_items::Reference;
+use crate::schema::Day;
+
+fn one() -> u32 { 1 }
+
+/// Who is invoicing and how invoices are numbered and booked.
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Invoicing {
+  pub seller: String,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub address: Vec<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub org_number: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub vat_number: Option<String>,
+  // How to pay, such as a bankgiro number
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub payment: Option<String>,
+  pub debtor_account: String,
+  // Put before the number, such as "2023-"
+  #[serde(default)]
+  pub prefix: String,
+  #[serde(default = "one")]
+  pub first_number: u32,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct InvoiceLine {
+  pub description: String,
+  pub quantity: Decimal,
+  // Per unit, without VAT
+  pub price: Decimal,
+  pub vat: VatCode,
+  // The income account it is booked on
+  pub account: String,
+}
+impl InvoiceLine {
+  pub fn net(&self) -> Decimal {
+    (self.quantity * self.price).round_dp(2)
+  }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Invoice {
+  pub number: u32,
+  pub customer: String,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub address: Vec<String>,
+  #[schemars(with = "Day")]
+  pub date: Date,
+  #[schemars(with = "Day")]
+  pub due: Date,
+  pub lines: Vec<InvoiceLine>,
+}
+
+// The VAT charged for a code, reverse charge sales have none
+fn rate(code: VatCode) -> Decimal {
+  match code {
+    VatCode::ReverseCharge => Decimal::ZERO,
+    code => code.rate(),
+  }
+}
+
+impl Invoice {
+  pub fn id(&self, invoicing: &Invoicing) -> String {
+    format!("{}{}", invoicing.prefix, self.number)
+  }
+
+  /// Net sum and VAT per income account and VAT code.
+  pub fn parts(&self) -> Vec<(String, VatCode, Decimal, Decimal)> {
+    let mut parts: Vec<(String, VatCode, Decimal, Decimal)> = Vec::new();
+    for line in &self.lines {
+      match parts.iter_mut().find(|(a, c, _, _)| a == &line.account && *c == line.vat) {
+        Some((_, _, net, _)) => *net += line.net(),
+        None => parts.push((line.account.clone(), line.vat, line.net(), Decimal::ZERO)),
+      }
+    }
+    for (_, code, net, vat) in parts.iter_mut() {
+      *vat = (*net * rate(*code)).round_dp(2);
+    }
+    parts
+  }
+
+  pub fn total(&self) -> Decimal {
+    self.parts().iter().map(|(_, _, net, vat)| net + vat).sum()
+  }
+
+  pub fn transaction(&self, invoicing: &Invoicing) -> Transaction {
+    let parts = self.parts();
+    let mut transfers = vec![(invoicing.debtor_account.clone(), self.total())];
+    let mut vat = Vec::new();
+    for (account, code, net, line_vat) in parts {
+      transfers.push((account.clone(), -(net + line_vat)));
+      vat.push((account, code));
+    }
+    Transaction{
+      name: format!("Invoice {} to {}", self.id(invoicing), self.customer),
+      date: self.date,
+      transfers,
+      vat,
+      accrue: None,
+      reference: Some(Reference{
+        id: self.id(invoicing),
+        due: Some(self.due),
+        counterparty: Some(self.customer.clone()),
+      }),
+      references: Vec::new(),
+      comments: Default::default(),
+      generated_by: Some(format!("invoice: {}", self.id(invoicing))),
+      source: None,
+    }
+  }
+}
+
+/// Panic unless the invoice numbers are unique and without gaps from the first
+/// number.
+pub fn validate_numbers(invoices: &[Invoice], invoicing: &Invoicing) {
+  let mut numbers: Vec<u32> = invoices.iter().map(|i| i.number).collect();
+  numbers.sort();
+  if let Some(number) = numbers.first().filter(|n| **n < invoicing.first_number) {
+    panic!("Invoice number {} is below the first number {}.", number, invoicing.first_number);
+  }
+  for (expected, number) in (invoicing.first_number..).zip(&numbers) {
+    if *number < expected {
+      panic!("Invoice number {} is used more than once.", number);
+    }
+    if *number > expected {
+      panic!("Invoice numbers must be sequential, {} is missing.", expected);
+    }
+  }
+}
+
+fn percent(code: VatCode) -> String {
+  match code {
+    VatCode::Exempt => "exempt".to_owned(),
+    VatCode::ReverseCharge => "reverse".to_owned(),
+    code => format!("{}%", (code.rate() * Decimal::ONE_HUNDRED).normalize()),
+  }
+}
+
+// VAT per rate, for the totals
+fn vat_per_code(parts: &[(String, VatCode, Decimal, Decimal)]) -> Vec<(VatCode, Decimal)> {
+  let mut per_code: Vec<(VatCode, Decimal)> = Vec::new();
+  for (_, code, _, vat) in parts {
+    if rate(*code).is_zero() { continue; }
+    match per_code.iter_mut().find(|(c, _)| c == code) {
+      Some((_, sum)) => *sum += vat,
+      None => per_code.push((*code, *vat)),
+    }
+  }
+  per_code
+}
+
+pub fn render_text(invoice: &Invoice, invoicing: &Invoicing) -> String {
+  let parts = invoice.parts();
+  let mut out = String::new();
+  writeln!(out, "INVOICE {}", invoice.id(invoicing)).unwrap();
+  writeln!(out).unwrap();
+  writeln!(out, "{}", invoicing.seller).unwrap();
+  for line in &invoicing.address { writeln!(out, "{}", line).unwrap(); }
+  if let Some(x) = &invoicing.org_number { writeln!(out, "Org. number: {}", x).unwrap(); }
+  if let Some(x) = &invoicing.vat_number { writeln!(out, "VAT number: {}", x).unwrap(); }
+  writeln!(out).unwrap();
+  writeln!(out, "To: {}", invoice.customer).unwrap();
+  for line in &invoice.address { writeln!(out, "    {}", line).unwrap(); }
+  writeln!(out).unwrap();
+  writeln!(out, "Date: {}", invoice.date).unwrap();
+  writeln!(out, "Due:  {}", invoice.due).unwrap();
+  writeln!(out).unwrap();
+  writeln!(out, "{:<30} {:>8} {:>10} {:>8} {:>12}", "Description", "Quantity", "Price", "VAT", "Amount").unwrap();
+  for line in &invoice.lines {
+    writeln!(out, "{:<30} {:>8} {:>10.2} {:>8} {:>12.2}",
+      line.description, line.quantity, line.price, percent(line.vat), line.net(),
+    ).unwrap();
+  }
+  writeln!(out).unwrap();
+  let net: Decimal = parts.iter().map(|(_, _, net, _)| net).sum();
+  writeln!(out, "{:>58} {:>12.2}", "Net:", net).unwrap();
+  for (code, vat) in vat_per_code(&parts) {
+    writeln!(out, "{:>58} {:>12.2}", format!("VAT {}:", percent(code)), vat).unwrap();
+  }
+  writeln!(out, "{:>58} {:>12.2}", "Total:", invoice.total()).unwrap();
+  if parts.iter().any(|(_, code, _, _)| *code == VatCode::ReverseCharge) {
+    writeln!(out).unwrap();
+    writeln!(out, "Reverse charge, the buyer is liable for VAT.").unwrap();
+  }
+  if let Some(x) = &invoicing.payment {
+    writeln!(out).unwrap();
+    writeln!(out, "Pay to {} with reference {}", x, invoice.id(invoicing)).unwrap();
+  }
+  out
+}
+
+pub fn escape_html(raw: &str) -> String {
+  raw.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+pub fn render_html(invoice: &Invoice, invoicing: &Invoicing) -> String {
+  let parts = invoice.parts();
+  let id = escape_html(&invoice.id(invoicing));
+  let mut out = String::new();
+  writeln!(out, "<!DOCTYPE html>").unwrap();
+  writeln!(out, "<html><head><meta charset=\"utf-8\"><title>Invoice {}</title>", id).unwrap();
+  writeln!(out, "<style>body {{ font-family: sans-serif; max-width: 50em; margin: 2em auto; }} \
+    table {{ border-collapse: collapse; width: 100%; }} th, td {{ padding: 0.3em; }} \
+    .num {{ text-align: right; }} thead {{ border-bottom: 1px solid black; }}</style>").unwrap();
+  writeln!(out, "</head><body>").unwrap();
+  writeln!(out, "<h1>Invoice {}</h1>", id).unwrap();
+  writeln!(out, "<p><strong>{}</strong>", escape_html(&invoicing.seller)).unwrap();
+  for line in &invoicing.address { writeln!(out, "<br>{}", escape_html(line)).unwrap(); }
+  if let Some(x) = &invoicing.org_number { writeln!(out, "<br>Org. number: {}", escape_html(x)).unwrap(); }
+  if let Some(x) = &invoicing.vat_number { writeln!(out, "<br>VAT number: {}", escape_html(x)).unwrap(); }
+  writeln!(out, "</p>").unwrap();
+  writeln!(out, "<p>To: <strong>{}</strong>", escape_html(&invoice.customer)).unwrap();
+  for line in &invoice.address { writeln!(out, "<br>{}", escape_html(line)).unwrap(); }
+  writeln!(out, "</p>").unwrap();
+  writeln!(out, "<p>Date: {}<br>Due: {}</p>", invoice.date, invoice.due).unwrap();
+  writeln!(out, "<table><thead><tr><th>Description</th><th class=\"num\">Quantity</th>\
+    <th class=\"num\">Price</th><th class=\"num\">VAT</th><th class=\"num\">Amount</th></tr></thead><tbody>").unwrap();
+  for line in &invoice.lines {
+    writeln!(out, "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{:.2}</td>\
+      <td class=\"num\">{}</td><td class=\"num\">{:.2}</td></tr>",
+      escape_html(&line.description), line.quantity, line.price, percent(line.vat), line.net(),
+    ).unwrap();
+  }
+  writeln!(out, "</tbody><tfoot>").unwrap();
+  let net: Decimal = parts.iter().map(|(_, _, net, _)| net).sum();
+  writeln!(out, "<tr><td colspan=\"4\" class=\"num\">Net</td><td class=\"num\">{:.2}</td></tr>", net).unwrap();
+  for (code, vat) in vat_per_code(&parts) {
+    writeln!(out, "<tr><td colspan=\"4\" class=\"num\">VAT {}</td><td class=\"num\">{:.2}</td></tr>", percent(code), vat).unwrap();
+  }
+  writeln!(out, "<tr><th colspan=\"4\" class=\"num\">Total</th><th class=\"num\">{:.2}</th></tr>", invoice.total()).unwrap();
+  writeln!(out, "</tfoot></table>").unwrap();
+  if parts.iter().any(|(_, code, _, _)| *code == VatCode::ReverseCharge) {
+    writeln!(out, "<p>Reverse charge, the buyer is liable for VAT.</p>").unwrap();
+  }
+  if let Some(x) = &invoicing.payment {
+    writeln!(out, "<p>Pay to {} with reference {}</p>", escape_html(x), id).unwrap();
+  }
+  writeln!(out, "</body></html>").unwrap();
+  out
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn invoicing(first_number: u32) -> Invoicing {
+    serde_yaml::from_str(&format!("seller: Me\ndebtor_account: debtors\nfirst_number: {}\n", first_number)).unwrap()
+  }
+
+  fn invoices(numbers: &[u32]) -> Vec<Invoice> {
+    numbers.iter().map(|number| serde_yaml::from_str(&format!("\
+number: {}
+customer: You
+date: 2023-01-10
+due: 2023-02-10
+lines: []
+", number)).unwrap()).collect()
+  }
+
+  #[test]
+  fn sequential() {
+    validate_numbers(&invoices(&[12, 10, 11]), &invoicing(10));
+    validate_numbers(&invoices(&[]), &invoicing(10));
+  }
+
+  #[test]
+  #[should_panic(expected = "Invoice number 11 is used more than once.")]
+  fn duplicate() {
+    validate_numbers(&invoices(&[10, 11, 11, 12]), &invoicing(10));
+  }
+
+  #[test]
+  #[should_panic(expected = "Invoice numbers must be sequential, 11 is missing.")]
+  fn gap() {
+    validate_numbers(&invoices(&[10, 12]), &invoicing(10));
+  }
+
+  #[test]
+  #[should_panic(expected = "Invoice number 5 is below the first number 10.")]
+  fn below_first_number() {
+    validate_numbers(&invoices(&[5, 10]), &invoicing(10));
+  }
+}
@@ -0,0 +1,248 @@
+//! Loans and mortgages paid off with a fixed amortization.
+//!
+//! Every month a payment is generated that pays the interest on the remaining
+//! principal and the amortization, from the money account to the creditor and
+//! interest expense accounts. A loan starting between payment days pays
+//! interest on the first payment only for the days since the start.
+
+use std::collections::BTreeMap;
+use serde::{
+  Serialize,
+  Deserialize,
+};
+use schemars::JsonSchema;
+use rust_decimal::Decimal;
+use time::{
+  Date,
+  Duration,
+};
+
+use crate::types::*;
+use crate::schema::Day;
+use crate::recurring::{
+  Schedule,
+  BusinessDay,
+};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct LoanAccounts {
+  // Where payments are made from
+  pub money: String,
+  // The loan itself
+  pub creditor: String,
+  // Where the interest is expensed
+  pub interest: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Loan {
+  pub name: String,
+  // The remaining principal at start, payments are made after it
+  pub principal: Decimal,
+  #[schemars(with = "Day")]
+  pub start: Date,
+  // Yearly interest rate in percent, from the given date and onwards
+  #[schemars(with = "BTreeMap<Day, Decimal>")]
+  pub rates: BTreeMap<Date, Decimal>,
+  // Principal paid off every month
+  pub amortization: Decimal,
+  pub payment_day: u8,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub business_day: Option<BusinessDay>,
+  pub accounts: LoanAccounts,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Payment {
+  pub date: Date,
+  pub interest: Decimal,
+  pub amortization: Decimal,
+  // Principal left after the payment
+  pub remaining: Decimal,
+}
+
+impl Loan {
+  fn rate(&self, date: Date) -> Decimal {
+    self.rates.range(..=date).next_back()
+      .map(|(_, rate)| *rate)
+      .unwrap_or_else(|| panic!("Loan {} has no interest rate for {}", self.name, date))
+  }
+
+  /// All payments until the given date or until the loan is paid off.
+  pub fn payments(&self, until: Date) -> Vec<Payment> {
+    let schedule = Schedule::Monthly(self.payment_day);
+    let mut payments = Vec::new();
+    let mut remaining = self.principal;
+    // The payment day on or before the start, the first period runs from it
+    let mut previous = self.start;
+    while !schedule.matches(previous) { previous -= Duration::days(1); }
+    let mut date = self.start + Duration::days(1);
+    while date <= until && remaining > Decimal::ZERO {
+      if schedule.matches(date) {
+        let booked = match self.business_day {
+          Some(b) => b.adjust(date),
+          None => date,
+        };
+        let mut interest = remaining * self.rate(booked) / Decimal::from(1200);
+        if payments.is_empty() {
+          // Only for the part of the first period since the start
+          interest = interest * Decimal::from((date - self.start).whole_days())
+            / Decimal::from((date - previous).whole_days())
+          ;
+        }
+        let interest = interest.round_dp(2);
+        let amortization = self.amortization.min(remaining);
+        remaining -= amortization;
+        payments.push(Payment{ date: booked, interest, amortization, remaining });
+      }
+      date += Duration::days(1);
+    }
+    payments
+  }
+
+  fn transaction(&self, payment: &Payment) -> Transaction {
+    Transaction{
+      name: self.name.clone(),
+      date: payment.date,
+      transfers: vec![
+        (self.accounts.money.clone(), -(payment.interest + payment.amortization)),
+        (self.accounts.creditor.clone(), payment.amortization),
+        (self.accounts.interest.clone(), payment.interest),
+      ],
+      vat: Vec::new(),
+      accrue: None,
+      reference: None,
+      references: Vec::new(),
+      comments: Default::default(),
+      generated_by: Some(format!("loan: {}", self.name)),
+      source: None,
+    }
+  }
+}
+
+/// The loan payments made within the given period.
+pub fn loan_payments(loans: &[Loan], period: &Period) -> Vec<Transaction> {
+  let mut transactions: Vec<Transaction> = loans.iter()
+    .flat_map(|loan| loan.payments(period.end).into_iter()
+      .filter(|payment| period.contains(payment.date))
+      .map(|payment| loan.transaction(&payment))
+      .collect::<Vec<_>>()
+    )
+    .collect()
+  ;
+  transactions.sort_by_key(|t| t.date);
+  transactions
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoanProjection {
+  pub name: String,
+  pub principal: Decimal,
+  pub start: Date,
+  pub total_interest: Decimal,
+  pub total_amortization: Decimal,
+  pub remaining: Decimal,
+  pub payments: Vec<Payment>,
+}
+
+/// Project every loan until the given date, or until paid off if none given.
+/// (Loans without amortization are projected 50 years.)
+pub fn loan_report(loans: &[Loan], until: Option<Date>) -> Vec<LoanProjection> {
+  loans.iter().map(|loan| {
+    let until = until.unwrap_or_else(|| loan.start.replace_year(loan.start.year() + 50)
+      .unwrap_or(loan.start + Duration::weeks(52 * 50))
+    );
+    let payments = loan.payments(until);
+    LoanProjection{
+      name: loan.name.clone(),
+      principal: loan.principal,
+      start: loan.start,
+      total_interest: payments.iter().map(|p| p.interest).sum(),
+      total_amortization: payments.iter().map(|p| p.amortization).sum(),
+      remaining: payments.last().map(|p| p.remaining).unwrap_or(loan.principal),
+      payments,
+    }
+  }).collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use time::Month;
+
+  fn date(month: Month, day: u8) -> Date {
+    Date::from_calendar_date(2023, month, day).unwrap()
+  }
+
+  fn d(s: &str) -> Decimal {
+    s.parse().unwrap()
+  }
+
+  fn loan() -> Loan {
+    Loan{
+      name: "car".to_owned(),
+      principal: d("1000"),
+      start: date(Month::January, 15),
+      rates: BTreeMap::from([
+        (date(Month::January, 1), d("3.5")),
+        (date(Month::March, 1), d("4")),
+      ]),
+      amortization: d("300"),
+      payment_day: 31,
+      business_day: Some(BusinessDay::Preceding),
+      accounts: LoanAccounts{
+        money: "money".to_owned(),
+        creditor: "loan".to_owned(),
+        interest: "interest".to_owned(),
+      },
+    }
+  }
+
+  #[test]
+  fn payments() {
+    let payments = loan().payments(date(Month::December, 31));
+    let rows: Vec<(Date, Decimal, Decimal, Decimal)> = payments.iter()
+      .map(|p| (p.date, p.interest, p.amortization, p.remaining))
+      .collect()
+    ;
+    // Interest is rounded to the cent each month, at the rate of the booked
+    // date, and the last payment only amortizes what remains. The first is
+    // for 16 of the 31 days since December 31st.
+    assert_eq!(rows, vec![
+      (date(Month::January, 31), d("1.51"), d("300"), d("700")),
+      (date(Month::February, 28), d("2.04"), d("300"), d("400")),
+      (date(Month::March, 31), d("1.33"), d("300"), d("100")),
+      (date(Month::April, 28), d("0.33"), d("100"), d("0")),
+    ]);
+  }
+
+  #[test]
+  fn transactions_in_period() {
+    let period = Period{ start: date(Month::February, 1), end: date(Month::March, 31) };
+    let transactions = loan_payments(&[loan()], &period);
+    assert_eq!(transactions.len(), 2);
+    assert_eq!(transactions[0].transfers, vec![
+      ("money".to_owned(), d("-302.04")),
+      ("loan".to_owned(), d("300")),
+      ("interest".to_owned(), d("2.04")),
+    ]);
+    let report = loan_report(&[loan()], None);
+    assert_eq!(report[0].total_interest, d("5.21"));
+    assert_eq!(report[0].remaining, Decimal::ZERO);
+  }
+
+  #[test]
+  fn first_period() {
+    // Starting on a payment day gives a full month
+    let full = Loan{ start: date(Month::January, 31), ..loan() };
+    assert_eq!(full.payments(date(Month::March, 1))[0].interest, d("2.92"));
+    // The period is between the scheduled days, even if the payment is moved
+    // (December 29th 2023 is a friday, 30 of 30 days since November 30th)
+    let moved = Loan{ start: date(Month::November, 30), payment_day: 30, ..loan() };
+    assert_eq!(moved.payments(date(Month::December, 31))[0].date, date(Month::December, 29));
+    assert_eq!(moved.payments(date(Month::December, 31))[0].interest, d("3.33"));
+    let late = Loan{ start: date(Month::February, 20), payment_day: 5, ..loan() };
+    // 13 of 28 days, at 4% since it is booked on friday March 3rd
+    assert_eq!(late.payments(date(Month::March, 31))[0].interest, d("1.55"));
+  }
+}
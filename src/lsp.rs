@@ -0,0 +1,734 @@
+//! A language server for the files of a bookkeeping, over stdio.
+//!
+//! Each open document is checked on its own for invalid YAML (such as invalid
+//! dates), unbalanced transactions and undeclared accounts. The whole
+//! bookkeeping is then loaded with the open documents in place of the saved
+//! files, reporting why it is invalid on bookkeeping.yaml. Completion offers
+//! the declared account names, hover on a transaction shows the balances after
+//! it and go-to-definition opens the file named on the line.
+
+use std::collections::{
+  BTreeMap,
+  HashMap,
+  HashSet,
+};
+use std::path::{
+  Path,
+  PathBuf,
+};
+use rust_decimal::Decimal;
+use lsp_server::{
+  Connection,
+  ErrorCode,
+  Message,
+  Notification,
+  Request,
+  RequestId,
+  Response,
+};
+use lsp_types::{
+  CompletionItem,
+  CompletionItemKind,
+  CompletionOptions,
+  CompletionParams,
+  Diagnostic,
+  DiagnosticSeverity,
+  DidChangeTextDocumentParams,
+  DidCloseTextDocumentParams,
+  DidOpenTextDocumentParams,
+  GotoDefinitionParams,
+  Hover,
+  HoverContents,
+  HoverParams,
+  HoverProviderCapability,
+  Location,
+  MarkupContent,
+  MarkupKind,
+  OneOf,
+  Position,
+  PublishDiagnosticsParams,
+  Range,
+  ServerCapabilities,
+  TextDocumentSyncCapability,
+  TextDocumentSyncKind,
+  Url,
+  notification::Notification as _,
+  request::Request as _,
+};
+
+use crate::types::*;
+use crate::file_io::FileIO;
+use crate::calculate::*;
+use crate::fmt::{
+  indent,
+  is_comment_or_blank,
+  split_key,
+};
+use crate::add::inlined_list;
+use crate::edit::item_ranges;
+use crate::{
+  panic_message,
+  load,
+};
+
+// Reads the files of the bookkeeping relative to its directory, using the
+// open documents over what is saved
+struct OverlayIO<'a> {
+  root: &'a Path,
+  open: &'a HashMap<PathBuf, String>,
+}
+impl FileIO for OverlayIO<'_> {
+  fn read_path(&mut self, path: &Path) -> String {
+    let path = self.root.join(path);
+    match self.open.get(&path) {
+      Some(text) => text.clone(),
+      None => std::fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("File not found: {}", path.display())),
+    }
+  }
+  // Edits are sent to the editor instead
+  fn write_path(&mut self, path: &Path, _: &str) -> Result<(), String> {
+    Err(format!("The language server doesn't write {}", path.display()))
+  }
+}
+
+// The directory of the bookkeeping the file belongs to
+fn root_of(path: &Path) -> Option<PathBuf> {
+  path.ancestors().skip(1)
+    .find(|dir| dir.join("bookkeeping.yaml").is_file())
+    .map(|dir| dir.to_owned())
+}
+
+fn read(path: &Path, open: &HashMap<PathBuf, String>) -> Option<String> {
+  open.get(path).cloned().or_else(|| std::fs::read_to_string(path).ok())
+}
+
+fn line_range(line: usize) -> Range {
+  Range{
+    start: Position{ line: line as u32, character: 0 },
+    end: Position{ line: line as u32 + 1, character: 0 },
+  }
+}
+
+fn error(range: Range, message: String) -> Diagnostic {
+  Diagnostic{
+    range,
+    severity: Some(DiagnosticSeverity::ERROR),
+    source: Some("bookkeep".to_owned()),
+    message,
+    ..Default::default()
+  }
+}
+
+fn yaml_error(e: &serde_yaml::Error) -> Diagnostic {
+  let line = e.location().map(|l| l.line().saturating_sub(1)).unwrap_or(0);
+  error(line_range(line), e.to_string())
+}
+
+// The declared accounts with their types, from the root file
+fn declared_accounts(root: &Path, open: &HashMap<PathBuf, String>) -> Option<BTreeMap<String, AccountType>> {
+  let raw = read(&root.join("bookkeeping.yaml"), open)?;
+  let book: Bookkeeping = serde_yaml::from_str(&raw).ok()?;
+  Some(book.accounts.iter()
+    .flat_map(|(t, accounts)| accounts.iter().map(|a| (a.name().to_owned(), *t)))
+    .collect()
+  )
+}
+
+// The line ranges of the transactions in a transaction file
+fn file_items(lines: &[&str]) -> Vec<(usize, usize)> {
+  let item_indent = lines.iter().find(|l| !is_comment_or_blank(l)).map(|l| indent(l)).unwrap_or(0);
+  item_ranges(lines, 0, lines.len(), item_indent)
+}
+
+// The line ranges of the transactions inlined into each grouping of the root
+// file
+fn inlined_items(lines: &[&str], book: &Bookkeeping) -> Vec<(String, Vec<(usize, usize)>)> {
+  book.groupings.iter()
+    .filter(|g| matches!(g.transactions, Transactions::Inlined(_)))
+    .filter_map(|g| {
+      let (start, end, item_indent) = inlined_list(lines, &g.name).ok()?;
+      Some((g.name.clone(), item_ranges(lines, start, end, item_indent)))
+    })
+    .collect()
+}
+
+fn transaction_diagnostics(
+  lines: &[&str],
+  items: &[(usize, usize)],
+  transactions: &[Transaction],
+  accounts: Option<&BTreeMap<String, AccountType>>,
+) -> Vec<Diagnostic> {
+  let mut out = Vec::new();
+  for (i, transaction) in transactions.iter().enumerate() {
+    let (start, end) = items.get(i).copied().unwrap_or((0, 0));
+    let sum: Decimal = transaction.transfers.iter().map(|(_, amount)| amount).sum();
+    if !sum.is_zero() {
+      out.push(error(line_range(start), format!("Transaction {} doesn't sum to 0 (sum: {})", transaction.name, sum)));
+    }
+    let accounts = match accounts {
+      Some(accounts) => accounts,
+      None => continue,
+    };
+    for (account, _) in &transaction.transfers {
+      if accounts.contains_key(account) { continue; }
+      let line = (start..end)
+        .find(|l| split_key(lines[*l]).is_some_and(|(k, _)| k.trim_matches(|c| c == '"' || c == '\'') == account))
+        .unwrap_or(start)
+      ;
+      out.push(error(line_range(line), format!("Account {} isn't declared", account)));
+    }
+  }
+  out
+}
+
+// The problems found in the document on its own
+fn document_diagnostics(path: &Path, text: &str, root: &Path, open: &HashMap<PathBuf, String>) -> Vec<Diagnostic> {
+  let lines: Vec<&str> = text.lines().collect();
+  if path == root.join("bookkeeping.yaml") {
+    let book: Bookkeeping = match serde_yaml::from_str(text) {
+      Ok(book) => book,
+      Err(e) => return vec![yaml_error(&e)],
+    };
+    let accounts = declared_accounts(root, open);
+    let mut out = Vec::new();
+    for (name, items) in inlined_items(&lines, &book) {
+      let grouping = book.groupings.iter().find(|g| g.name == name).unwrap();
+      if let Transactions::Inlined(transactions) = &grouping.transactions {
+        out.extend(transaction_diagnostics(&lines, &items, transactions, accounts.as_ref()));
+      }
+    }
+    return out;
+  }
+  // Only transaction files are lists, budgets and such are left to loading
+  match serde_yaml::from_str::<serde_yaml::Value>(text) {
+    Err(e) => vec![yaml_error(&e)],
+    Ok(serde_yaml::Value::Sequence(_)) => match serde_yaml::from_str::<Vec<Transaction>>(text) {
+      Err(e) => vec![yaml_error(&e)],
+      Ok(transactions) => transaction_diagnostics(
+        &lines,
+        &file_items(&lines),
+        &transactions,
+        declared_accounts(root, open).as_ref(),
+      ),
+    },
+    Ok(_) => Vec::new(),
+  }
+}
+
+// Run the function, returning the message if it panics. The panic hook is
+// left as set when starting, since swapping it here would race with the
+// threads reading and writing the messages.
+fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+  std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(panic_message)
+}
+
+fn summarize(root: &Path, open: &HashMap<PathBuf, String>) -> Result<SummedBookkeeping, String> {
+  catch_panic(|| calculate(load(&mut OverlayIO{ root, open })))
+}
+
+// The diagnostics of each open document of the bookkeeping, and of its root
+// file with why the bookkeeping fails to load
+fn bookkeeping_diagnostics(root: &Path, open: &HashMap<PathBuf, String>) -> Vec<(PathBuf, Vec<Diagnostic>)> {
+  let root_path = root.join("bookkeeping.yaml");
+  let mut out: Vec<(PathBuf, Vec<Diagnostic>)> = open.iter()
+    .filter(|(path, _)| path.starts_with(root) && **path != root_path)
+    .map(|(path, text)| (path.clone(), document_diagnostics(path, text, root, open)))
+    .collect()
+  ;
+  let mut root_diagnostics = read(&root_path, open)
+    .map(|text| document_diagnostics(&root_path, &text, root, open))
+    .unwrap_or_default()
+  ;
+  if let Err(e) = summarize(root, open) {
+    root_diagnostics.push(error(line_range(0), format!("The bookkeeping fails to load: {}", e)));
+  }
+  out.push((root_path, root_diagnostics));
+  out
+}
+
+// The source of the transaction on the line, if any
+fn source_at(path: &Path, text: &str, line: usize, root: &Path) -> Option<Source> {
+  let lines: Vec<&str> = text.lines().collect();
+  let within = |items: &[(usize, usize)]| items.iter().position(|(start, end)| *start <= line && line < *end);
+  if path == root.join("bookkeeping.yaml") {
+    let book: Bookkeeping = serde_yaml::from_str(text).ok()?;
+    return inlined_items(&lines, &book).into_iter()
+      .find_map(|(grouping, items)| within(&items).map(|index| Source::Inlined{ grouping, index }))
+    ;
+  }
+  let index = within(&file_items(&lines))?;
+  Some(Source::Path{ path: path.strip_prefix(root).ok()?.to_owned(), index })
+}
+
+// The transfers of the transaction on the line with the balances after them
+fn hover(path: &Path, line: usize, root: &Path, open: &HashMap<PathBuf, String>) -> Option<String> {
+  let source = source_at(path, &read(path, open)?, line, root)?;
+  let summary = summarize(root, open).ok()?;
+  let transfers: Vec<(&SummedAccount, &Transfer)> = summary.total.account_types.iter()
+    .flat_map(|(_, _, accounts)| accounts)
+    .flat_map(|account| account.transfers.iter().map(move |t| (account, t)))
+    .filter(|(_, t)| t.source.as_ref() == Some(&source))
+    .collect()
+  ;
+  let (_, first) = transfers.first()?;
+  let mut out = format!("**{}** {}\n\n| Account | Amount | Balance after |\n|---|---:|---:|\n", first.name, first.date);
+  for (account, transfer) in transfers {
+    out.push_str(&format!("| {} | {} | {} |\n", account.label(), transfer.amount, transfer.resulting_balance));
+  }
+  Some(out)
+}
+
+// The file named under the cursor, relative to the bookkeeping
+fn definition(path: &Path, position: Position, root: &Path, open: &HashMap<PathBuf, String>) -> Option<Location> {
+  let text = read(path, open)?;
+  let line: Vec<char> = text.lines().nth(position.line as usize)?.chars().collect();
+  let is_path = |c: &char| !c.is_whitespace() && !"[]{},\"'#".contains(*c);
+  let cursor = (position.character as usize).min(line.len());
+  let start = line[..cursor].iter().rposition(|c| !is_path(c)).map(|i| i + 1).unwrap_or(0);
+  let end = line[cursor..].iter().position(|c| !is_path(c)).map(|i| cursor + i).unwrap_or(line.len());
+  let word: String = line[start..end].iter().collect();
+  let target = root.join(&word);
+  if word.is_empty() || !target.is_file() { return None; }
+  Some(Location{
+    uri: Url::from_file_path(target).ok()?,
+    range: Range::default(),
+  })
+}
+
+// The declared accounts, if the cursor is where an account name is written
+fn completion(path: &Path, position: Position, root: &Path, open: &HashMap<PathBuf, String>) -> Vec<CompletionItem> {
+  let text = read(path, open).unwrap_or_default();
+  let line = text.lines().nth(position.line as usize).unwrap_or("");
+  let before: String = line.chars().take(position.character as usize).collect();
+  let typed = before.trim_start();
+  let typed = typed.strip_prefix("- ").unwrap_or(typed);
+  // Account names may contain colons, but not the space after a key
+  if typed.contains(|c: char| c.is_whitespace() || c == '#' || c == '"' || c == '\'') { return Vec::new(); }
+  declared_accounts(root, open).unwrap_or_default().into_iter()
+    .map(|(name, account_type)| CompletionItem{
+      label: name,
+      kind: Some(CompletionItemKind::VALUE),
+      detail: Some(format!("{:?}", account_type)),
+      ..Default::default()
+    })
+    .collect()
+}
+
+fn handle_request(open: &HashMap<PathBuf, String>, request: Request) -> Response {
+  let id = request.id.clone();
+  let result = catch_panic(|| -> Result<serde_json::Value, (ErrorCode, String)> {
+    let position_in = |uri: &Url| -> Result<(PathBuf, PathBuf), (ErrorCode, String)> {
+      let path = uri.to_file_path().map_err(|_| (ErrorCode::InvalidParams, format!("Not a file: {}", uri)))?;
+      let root = root_of(&path)
+        .ok_or_else(|| (ErrorCode::InvalidParams, format!("No bookkeeping.yaml above {}", path.display())))?
+      ;
+      Ok((path, root))
+    };
+    let extract = |e| (ErrorCode::InvalidParams, format!("Invalid request: {:?}", e));
+    match request.method.as_str() {
+      lsp_types::request::Completion::METHOD => {
+        let (_, params): (RequestId, CompletionParams) = request.extract(lsp_types::request::Completion::METHOD)
+          .map_err(extract)?
+        ;
+        let at = params.text_document_position;
+        let (path, root) = position_in(&at.text_document.uri)?;
+        Ok(serde_json::to_value(completion(&path, at.position, &root, open)).unwrap())
+      },
+      lsp_types::request::HoverRequest::METHOD => {
+        let (_, params): (RequestId, HoverParams) = request.extract(lsp_types::request::HoverRequest::METHOD)
+          .map_err(extract)?
+        ;
+        let at = params.text_document_position_params;
+        let (path, root) = position_in(&at.text_document.uri)?;
+        let hover = hover(&path, at.position.line as usize, &root, open).map(|value| Hover{
+          contents: HoverContents::Markup(MarkupContent{ kind: MarkupKind::Markdown, value }),
+          range: None,
+        });
+        Ok(serde_json::to_value(hover).unwrap())
+      },
+      lsp_types::request::GotoDefinition::METHOD => {
+        let (_, params): (RequestId, GotoDefinitionParams) = request.extract(lsp_types::request::GotoDefinition::METHOD)
+          .map_err(extract)?
+        ;
+        let at = params.text_document_position_params;
+        let (path, root) = position_in(&at.text_document.uri)?;
+        Ok(serde_json::to_value(definition(&path, at.position, &root, open)).unwrap())
+      },
+      method => Err((ErrorCode::MethodNotFound, format!("Unsupported request {}", method))),
+    }
+  });
+  match result {
+    Ok(Ok(value)) => Response::new_ok(id, value),
+    Ok(Err((code, e))) => Response::new_err(id, code as i32, e),
+    Err(e) => Response::new_err(id, ErrorCode::InternalError as i32, e),
+  }
+}
+
+// Update the open documents, returning the bookkeeping that changed
+fn handle_notification(open: &mut HashMap<PathBuf, String>, notification: Notification) -> Option<PathBuf> {
+  let path = |uri: &Url| uri.to_file_path().ok();
+  match notification.method.as_str() {
+    lsp_types::notification::DidOpenTextDocument::METHOD => {
+      let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+      let path = path(&params.text_document.uri)?;
+      open.insert(path.clone(), params.text_document.text);
+      root_of(&path)
+    },
+    lsp_types::notification::DidChangeTextDocument::METHOD => {
+      let params: DidChangeTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+      let path = path(&params.text_document.uri)?;
+      // With full sync the last change is the whole document
+      let text = params.content_changes.into_iter().last()?.text;
+      open.insert(path.clone(), text);
+      root_of(&path)
+    },
+    lsp_types::notification::DidCloseTextDocument::METHOD => {
+      let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+      let path = path(&params.text_document.uri)?;
+      open.remove(&path);
+      root_of(&path)
+    },
+    lsp_types::notification::DidSaveTextDocument::METHOD => {
+      let params: lsp_types::DidSaveTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+      root_of(&path(&params.text_document.uri)?)
+    },
+    _ => None,
+  }
+}
+
+/// Serve the language server protocol on stdin and stdout until shut down.
+pub fn run_lsp() {
+  // Invalid bookkeepings panic, which is reported to the client instead
+  std::panic::set_hook(Box::new(|_| {}));
+  let (connection, io_threads) = Connection::stdio();
+  serve(&connection);
+  // The writer thread stops when the connection is dropped
+  drop(connection);
+  io_threads.join().expect("Failed to shut down the language server");
+}
+
+// Initialize the connection and answer the client until it shuts down
+fn serve(connection: &Connection) {
+  let capabilities = serde_json::to_value(ServerCapabilities{
+    text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+    completion_provider: Some(CompletionOptions::default()),
+    hover_provider: Some(HoverProviderCapability::Simple(true)),
+    definition_provider: Some(OneOf::Left(true)),
+    ..Default::default()
+  }).unwrap();
+  connection.initialize(capabilities).expect("Failed to initialize the language server");
+  let mut open: HashMap<PathBuf, String> = HashMap::new();
+  // Documents given diagnostics, to clear them when fixed or closed
+  let mut published: HashSet<PathBuf> = HashSet::new();
+  for message in &connection.receiver {
+    match message {
+      Message::Request(request) => {
+        if connection.handle_shutdown(&request).expect("Language server protocol error") { break; }
+        let response = handle_request(&open, request);
+        connection.sender.send(Message::Response(response)).unwrap();
+      },
+      Message::Notification(notification) => {
+        let root = match handle_notification(&mut open, notification) {
+          Some(root) => root,
+          None => continue,
+        };
+        let mut all = bookkeeping_diagnostics(&root, &open);
+        for path in published.iter().filter(|p| p.starts_with(&root)) {
+          if !all.iter().any(|(p, _)| p == path) { all.push((path.clone(), Vec::new())); }
+        }
+        for (path, diagnostics) in all {
+          if diagnostics.is_empty() {
+            if !published.remove(&path) { continue; }
+          } else {
+            published.insert(path.clone());
+          }
+          let uri = match Url::from_file_path(&path) {
+            Ok(uri) => uri,
+            Err(_) => continue,
+          };
+          let params = PublishDiagnosticsParams{ uri, diagnostics, version: None };
+          connection.sender.send(Message::Notification(Notification::new(
+            lsp_types::notification::PublishDiagnostics::METHOD.to_owned(),
+            params,
+          ))).unwrap();
+        }
+      },
+      Message::Response(_) => {},
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use lsp_types::{
+    CompletionResponse,
+    GotoDefinitionResponse,
+    TextDocumentIdentifier,
+    TextDocumentItem,
+    TextDocumentPositionParams,
+    TextDocumentContentChangeEvent,
+    VersionedTextDocumentIdentifier,
+  };
+
+  const BOOK: &str = "\
+version: 1
+name: test
+accounts:
+  asset: [money]
+  income: [salary]
+  expense: [rent]
+account_sums: {}
+groupings:
+- name: January
+  transactions: !Paths [january.yaml]
+";
+
+  const JANUARY: &str = "\
+- name: Salary
+  date: 2023-01-25
+  transfers:
+    money: 1000
+    salary: -1000
+- name: Rent
+  date: 2023-01-31
+  transfers:
+    rent: 400
+    money: -400
+";
+
+  // A bookkeeping in a directory of its own, removed when dropped
+  struct Dir(PathBuf);
+  impl Dir {
+    fn new(name: &str) -> Self {
+      let dir = std::env::temp_dir().join(format!("bookkeep-lsp-{}-{}", std::process::id(), name));
+      std::fs::create_dir_all(&dir).unwrap();
+      std::fs::write(dir.join("bookkeeping.yaml"), BOOK).unwrap();
+      std::fs::write(dir.join("january.yaml"), JANUARY).unwrap();
+      Dir(dir)
+    }
+    fn uri(&self, file: &str) -> Url {
+      Url::from_file_path(self.0.join(file)).unwrap()
+    }
+  }
+  impl Drop for Dir {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_dir_all(&self.0);
+    }
+  }
+
+  // The editor's end of a connection to a server on another thread
+  struct Client {
+    connection: Connection,
+    server: std::thread::JoinHandle<()>,
+    next_id: i32,
+    // The latest diagnostics published per document
+    diagnostics: HashMap<Url, Vec<Diagnostic>>,
+  }
+  impl Client {
+    // Returns the server's capabilities with the client
+    fn start() -> (serde_json::Value, Self) {
+      let (server, connection) = Connection::memory();
+      let server = std::thread::spawn(move || serve(&server));
+      let mut client = Client{ connection, server, next_id: 0, diagnostics: HashMap::new() };
+      let result = client.request("initialize", serde_json::json!({ "capabilities": {} })).result.unwrap();
+      client.notify("initialized", serde_json::json!({}));
+      (result, client)
+    }
+
+    fn notify(&self, method: &str, params: impl serde::Serialize) {
+      let notification = Notification::new(method.to_owned(), params);
+      self.connection.sender.send(Message::Notification(notification)).unwrap();
+    }
+
+    // Diagnostics for earlier notifications are published before the
+    // response, so they are all collected when this returns
+    fn request(&mut self, method: &str, params: impl serde::Serialize) -> Response {
+      let id = RequestId::from(self.next_id);
+      self.next_id += 1;
+      let request = Request::new(id.clone(), method.to_owned(), params);
+      self.connection.sender.send(Message::Request(request)).unwrap();
+      loop {
+        match self.connection.receiver.recv().unwrap() {
+          Message::Response(response) if response.id == id => return response,
+          Message::Notification(n) if n.method == lsp_types::notification::PublishDiagnostics::METHOD => {
+            let params: PublishDiagnosticsParams = serde_json::from_value(n.params).unwrap();
+            self.diagnostics.insert(params.uri, params.diagnostics);
+          },
+          message => panic!("Unexpected message {:?}", message),
+        }
+      }
+    }
+
+    // Wait until the notifications sent so far are handled, an unsupported
+    // request is answered all the same
+    fn sync(&mut self) {
+      self.request("bookkeep/sync", serde_json::Value::Null);
+    }
+
+    fn at(uri: &Url, line: u32, character: u32) -> TextDocumentPositionParams {
+      TextDocumentPositionParams{
+        text_document: TextDocumentIdentifier{ uri: uri.clone() },
+        position: Position{ line, character },
+      }
+    }
+
+    fn open(&self, uri: &Url, text: &str) {
+      self.notify(lsp_types::notification::DidOpenTextDocument::METHOD, DidOpenTextDocumentParams{
+        text_document: TextDocumentItem{
+          uri: uri.clone(),
+          language_id: "yaml".to_owned(),
+          version: 0,
+          text: text.to_owned(),
+        },
+      });
+    }
+
+    fn change(&self, uri: &Url, text: &str) {
+      self.notify(lsp_types::notification::DidChangeTextDocument::METHOD, DidChangeTextDocumentParams{
+        text_document: VersionedTextDocumentIdentifier{ uri: uri.clone(), version: 1 },
+        content_changes: vec![TextDocumentContentChangeEvent{ range: None, range_length: None, text: text.to_owned() }],
+      });
+    }
+
+    fn completion(&mut self, uri: &Url, line: u32, character: u32) -> Vec<String> {
+      let params = CompletionParams{
+        text_document_position: Self::at(uri, line, character),
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+        context: None,
+      };
+      let result = self.request(lsp_types::request::Completion::METHOD, params).result.unwrap();
+      match serde_json::from_value(result).unwrap() {
+        CompletionResponse::Array(items) => items.into_iter().map(|i| i.label).collect(),
+        CompletionResponse::List(list) => list.items.into_iter().map(|i| i.label).collect(),
+      }
+    }
+
+    fn hover(&mut self, uri: &Url, line: u32) -> Option<String> {
+      let params = HoverParams{
+        text_document_position_params: Self::at(uri, line, 0),
+        work_done_progress_params: Default::default(),
+      };
+      let result = self.request(lsp_types::request::HoverRequest::METHOD, params).result.unwrap();
+      let hover: Option<Hover> = serde_json::from_value(result).unwrap();
+      hover.map(|h| match h.contents {
+        HoverContents::Markup(markup) => markup.value,
+        contents => panic!("Unexpected hover {:?}", contents),
+      })
+    }
+
+    fn definition(&mut self, uri: &Url, line: u32, character: u32) -> Option<Url> {
+      let params = GotoDefinitionParams{
+        text_document_position_params: Self::at(uri, line, character),
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+      };
+      let result = self.request(lsp_types::request::GotoDefinition::METHOD, params).result.unwrap();
+      let location: Option<GotoDefinitionResponse> = serde_json::from_value(result).unwrap();
+      location.map(|l| match l {
+        GotoDefinitionResponse::Scalar(location) => location.uri,
+        l => panic!("Unexpected definition {:?}", l),
+      })
+    }
+
+    fn shutdown(mut self) {
+      let response = self.request("shutdown", serde_json::Value::Null);
+      assert!(response.error.is_none());
+      self.notify("exit", serde_json::Value::Null);
+      self.server.join().unwrap();
+    }
+  }
+
+  #[test]
+  fn initialize() {
+    let (result, client) = Client::start();
+    let capabilities: ServerCapabilities = serde_json::from_value(result["capabilities"].clone()).unwrap();
+    assert_eq!(capabilities.text_document_sync, Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)));
+    assert!(capabilities.completion_provider.is_some());
+    assert_eq!(capabilities.hover_provider, Some(HoverProviderCapability::Simple(true)));
+    assert_eq!(capabilities.definition_provider, Some(OneOf::Left(true)));
+    client.shutdown();
+  }
+
+  #[test]
+  fn diagnostics() {
+    let dir = Dir::new("diagnostics");
+    let (_, mut client) = Client::start();
+    let january = dir.uri("january.yaml");
+    let root = dir.uri("bookkeeping.yaml");
+    client.open(&january, &JANUARY.replace("rent: 400", "food: 500"));
+    client.sync();
+    let messages = |diagnostics: &[Diagnostic]| diagnostics.iter()
+      .map(|d| (d.range.start.line, d.message.clone()))
+      .collect::<Vec<_>>()
+    ;
+    assert_eq!(messages(&client.diagnostics[&january]), [
+      (5, "Transaction Rent doesn't sum to 0 (sum: 100)".to_owned()),
+      (8, "Account food isn't declared".to_owned()),
+    ]);
+    let root_messages = messages(&client.diagnostics[&root]);
+    assert_eq!(root_messages.len(), 1);
+    assert!(root_messages[0].1.starts_with("The bookkeeping fails to load: "));
+    // Fixing it clears the diagnostics
+    client.change(&january, JANUARY);
+    client.sync();
+    assert!(client.diagnostics[&january].is_empty());
+    assert!(client.diagnostics[&root].is_empty());
+    client.shutdown();
+  }
+
+  #[test]
+  fn invalid_yaml() {
+    let dir = Dir::new("invalid_yaml");
+    let (_, mut client) = Client::start();
+    let january = dir.uri("january.yaml");
+    client.open(&january, &JANUARY.replace("2023-01-31", "2023-02-31"));
+    client.sync();
+    let diagnostics = &client.diagnostics[&january];
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].range.start.line, 6);
+    client.shutdown();
+  }
+
+  #[test]
+  fn completion() {
+    let dir = Dir::new("completion");
+    let (_, mut client) = Client::start();
+    let january = dir.uri("january.yaml");
+    client.open(&january, &JANUARY.replace("    rent: 400", "    re"));
+    assert_eq!(client.completion(&january, 8, 6), ["money", "rent", "salary"]);
+    // Not after the key
+    assert!(client.completion(&january, 9, 11).is_empty());
+    client.shutdown();
+  }
+
+  #[test]
+  fn hover() {
+    let dir = Dir::new("hover");
+    let (_, mut client) = Client::start();
+    let january = dir.uri("january.yaml");
+    let text = client.hover(&january, 7).unwrap();
+    assert!(text.starts_with("**Rent** 2023-01-31"), "{}", text);
+    assert!(text.contains("| money | -400 | 600 |"), "{}", text);
+    assert!(text.contains("| rent | 400 | 400 |"), "{}", text);
+    // The open document is used over the saved file
+    client.open(&january, &JANUARY.replace("400", "300"));
+    let text = client.hover(&january, 7).unwrap();
+    assert!(text.contains("| money | -300 | 700 |"), "{}", text);
+    assert_eq!(client.hover(&dir.uri("bookkeeping.yaml"), 0), None);
+    client.shutdown();
+  }
+
+  #[test]
+  fn definition() {
+    let dir = Dir::new("definition");
+    let (_, mut client) = Client::start();
+    let root = dir.uri("bookkeeping.yaml");
+    assert_eq!(client.definition(&root, 9, 28), Some(dir.uri("january.yaml")));
+    // Only files that exist
+    assert_eq!(client.definition(&root, 8, 10), None);
+    client.shutdown();
+  }
+}
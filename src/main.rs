@@ -7,25 +7,319 @@ mod file_io;
 use file_io::*;
 mod calculate;
 use calculate::*;
+mod vat;
+use vat::*;
+mod budget;
+use budget::*;
+mod recurring;
+mod loan;
+use loan::*;
+mod asset;
+use asset::*;
+mod accrual;
+mod sums;
+mod metrics;
+use metrics::*;
+mod open_items;
+use open_items::*;
+mod invoice;
+use invoice::*;
+mod migrate;
+use migrate::*;
+mod fmt;
+use fmt::*;
+mod add;
+use add::*;
+mod edit;
+use edit::*;
+mod lsp;
+use lsp::*;
+mod schema;
+use schema::*;
+mod query;
+use query::*;
+mod sqlite;
+use sqlite::*;
+mod xlsx;
+use xlsx::*;
+mod html;
+use html::*;
+mod serve;
+use serve::*;
+mod site;
+use site::*;
+mod pdf;
+use pdf::*;
+mod init;
+use init::*;
 mod tui;
 use tui::*;
 
 
-fn main() {
-  let mut io = StdFileIO{};
+const USAGE: &str = "\
+Usage: bookkeep [command]
+  (no command)  Calculate and show the bookkeeping in ./bookkeeping.yaml
+                (In the terminal a adds a transaction, e edits and d deletes the
+                selected transfer's transaction, / queries the transfers and q quits)
+  init <template> [directory]
+                Create a new bookkeeping from a template, in the current directory if none given
+  migrate       Upgrade ./bookkeeping.yaml and its files to the current file format
+  add           Enter a new transaction and add it to the grouping covering its date
+  serve [port]  Serve a web view of the bookkeeping on localhost, by default on port 8000
+                (It reloads when the files change)
+  lsp           Run a language server for the bookkeeping's files over stdio
+  schema [bookkeeping|transactions|budget]
+                Print the JSON Schema of the file format, bookkeeping.yaml if none given
+  fmt [--check] [files]
+                Format the transactions, by default in ./bookkeeping.yaml and all files it includes
+                (With --check nothing is written, exits with 1 if any file would change)
+  query <query> [table|csv|json]
+                Print the transfers matching the query, such as
+                \"account:food amount>100 date:2023-Q1 -comment:receipt\"
+                (Terms: account:NAME type:TYPE sum:NAME date:FROM..TO amount>N
+                name:REGEX comment:KEY[=REGEX], combined with not, - and or)
+  export sqlite <file>
+                Write the transactions, accounts and sums into a new SQLite database
+  export xlsx <file>
+                Write an overview and a sheet per grouping into a workbook, which
+                LibreOffice and Excel open
+  report vat    Print the VAT return boxes for each grouping and the total
+  report budget Print budget against actual sums for each grouping and the total
+  report metrics
+                Print the metrics for each grouping and the total
+  report loans [until]
+                Project the loans' payments until the date or until paid off
+  report assets [date]
+                Print the asset register as of the date or the last transaction
+  report aging [date]
+                Print open items by days overdue as of the date or the last transaction
+  report html <directory>
+                Write the totals, groupings, accounts, balance sheet and income statement
+                as HTML pages into the directory, with the files named in comments
+  report pdf <file> [journal|ledger|balance-sheet|income-statement]...
+                Write the reports, all if none given, into a PDF for printing and archiving
+  invoice <number> [text|html]
+                Render the invoice with the given number
+";
 
+// Run the function, returning the message if it panics (as it does on an
+// invalid bookkeeping) instead of printing it
+fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+  let hook = std::panic::take_hook();
+  std::panic::set_hook(Box::new(|_| {}));
+  let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+  std::panic::set_hook(hook);
+  result.map_err(panic_message)
+}
+
+// The message a panic was started with
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+  match payload.downcast::<String>() {
+    Ok(message) => *message,
+    Err(e) => e.downcast_ref::<&str>().map(|m| m.to_string()).unwrap_or_default(),
+  }
+}
+
+fn load(io: &mut impl FileIO) -> RealBookkeeping {
   let raw = io.read_path(std::path::Path::new("bookkeeping.yaml"));
+  check_version(&raw);
   let parsed: Bookkeeping = serde_yaml::from_str(&raw)
-    .expect("Invalid format at year.yaml")
+    .expect("Invalid format at bookkeeping.yaml")
   ;
-  let real = parsed.realize(&mut io);
-  // Do all the calculations
-  let calc = calculate(real);
+  parsed.realize(io)
+}
 
-  if std::io::stdout().is_tty() {
-    run_tui(calc);
-  }
-  else {
-    println!("{}", serde_yaml::to_string(&calc).unwrap());
+fn parse_date(raw: &str) -> time::Date {
+  serde_yaml::from_str(raw)
+    .unwrap_or_else(|_| panic!("Invalid date {}, expected YYYY-MM-DD", raw))
+}
+
+// The given date, or else the date of the last transaction
+fn as_of_date(args: &[&str], real: &RealBookkeeping) -> time::Date {
+  args.first().map(|x| parse_date(x))
+    .or_else(|| real.groupings.iter().flat_map(|g| &g.transactions).map(|t| t.date).max())
+    .expect("No date given and no transactions to take it from")
+}
+
+fn main() {
+  let mut io = StdFileIO{};
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+
+  match args.as_slice() {
+    [] => {
+      let real = load(&mut io);
+      // Do all the calculations
+      let calc = calculate(real);
+
+      if std::io::stdout().is_tty() {
+        run_tui(calc);
+      }
+      else {
+        println!("{}", serde_yaml::to_string(&calc).unwrap());
+      }
+    },
+    ["report", "vat"] => {
+      let real = load(&mut io);
+      println!("{}", serde_yaml::to_string(&vat_report(&real)).unwrap());
+    },
+    ["report", "budget"] => {
+      let calc = calculate(load(&mut io));
+      println!("{}", serde_yaml::to_string(&budget_report(&calc)).unwrap());
+    },
+    ["report", "metrics"] => {
+      let calc = calculate(load(&mut io));
+      println!("{}", serde_yaml::to_string(&metrics_report(&calc)).unwrap());
+    },
+    ["report", "loans", until @ ..] if until.len() <= 1 => {
+      let real = load(&mut io);
+      let until = until.first().map(|x| parse_date(x));
+      println!("{}", serde_yaml::to_string(&loan_report(&real.loans, until)).unwrap());
+    },
+    ["report", "assets", as_of @ ..] if as_of.len() <= 1 => {
+      let real = load(&mut io);
+      let as_of = as_of_date(as_of, &real);
+      println!("{}", serde_yaml::to_string(&asset_report(&real.assets, as_of)).unwrap());
+    },
+    ["report", "aging", as_of @ ..] if as_of.len() <= 1 => {
+      let real = load(&mut io);
+      let as_of = as_of_date(as_of, &real);
+      println!("{}", serde_yaml::to_string(&aging_report(&real, as_of)).unwrap());
+    },
+    ["report", "html", dir] => {
+      let summary = calculate(load(&mut io));
+      let dir = std::path::Path::new(dir);
+      let pages = write_site(&summary, dir).unwrap_or_else(|e| panic!("{}", e));
+      println!("Wrote {} pages to {}", pages, dir.display());
+    },
+    ["report", "pdf", path, reports @ ..] => {
+      let real = load(&mut io);
+      let summary = calculate(real.clone());
+      let path = std::path::Path::new(path);
+      let pages = write_pdf(&real, &summary, reports, path).unwrap_or_else(|e| panic!("{}", e));
+      println!("Wrote {} pages to {}", pages, path.display());
+    },
+    ["invoice", number, format @ ..] if format.len() <= 1 => {
+      let real = load(&mut io);
+      let invoicing = real.invoicing.as_ref().expect("Invoicing isn't configured in bookkeeping.yaml");
+      let invoice = real.invoices.iter()
+        .find(|i| i.number.to_string() == *number || i.id(invoicing) == *number)
+        .unwrap_or_else(|| panic!("No invoice with number {}", number))
+      ;
+      match format.first().copied().unwrap_or("text") {
+        "text" => print!("{}", render_text(invoice, invoicing)),
+        "html" => print!("{}", render_html(invoice, invoicing)),
+        x => panic!("Unknown invoice format {}, expected text or html", x),
+      }
+    },
+    ["query", query, format @ ..] if format.len() <= 1 => {
+      let real = load(&mut io);
+      let query = parse_query(query, &real).unwrap_or_else(|e| panic!("{}", e));
+      let rows = query_rows(&real, &query);
+      match format.first().copied().unwrap_or("table") {
+        "table" => print!("{}", render_table(&rows)),
+        "csv" => print!("{}", render_csv(&rows)),
+        "json" => println!("{}", serde_json::to_string_pretty(&rows).unwrap()),
+        x => panic!("Unknown query format {}, expected table, csv or json", x),
+      }
+    },
+    ["export", "sqlite", path] => {
+      let real = load(&mut io);
+      let summary = calculate(real.clone());
+      let path = std::path::Path::new(path);
+      export_sqlite(&real, &summary, path).unwrap_or_else(|e| panic!("{}", e));
+      println!("Exported to {}", path.display());
+    },
+    ["export", "xlsx", path] => {
+      let real = load(&mut io);
+      let summary = calculate(real.clone());
+      let path = std::path::Path::new(path);
+      export_xlsx(&real, &summary, path).unwrap_or_else(|e| panic!("{}", e));
+      println!("Exported to {}", path.display());
+    },
+    ["init", template, dir @ ..] if dir.len() <= 1 => {
+      let dir = std::path::Path::new(dir.first().copied().unwrap_or("."));
+      let year = time::OffsetDateTime::now_utc().year();
+      for path in init(template, dir, year) {
+        println!("Created {}", path.display());
+      }
+    },
+    ["add"] => {
+      let real = load(&mut io);
+      let today = time::OffsetDateTime::now_utc().date();
+      let transaction = match prompt_transaction(&real.accounts, today) {
+        Some(t) => t,
+        None => std::process::exit(1),
+      };
+      validate_new(&transaction, &real.accounts).unwrap_or_else(|e| panic!("{}", e));
+      let path = add_transaction(&mut io, &transaction, |io| catch_panic(|| { calculate(load(io)); }))
+        .unwrap_or_else(|e| panic!("{}", e))
+      ;
+      println!("Added {} to {}", transaction.name, path.display());
+    },
+    ["serve", port @ ..] if port.len() <= 1 => {
+      let port = port.first()
+        .map(|p| p.parse().unwrap_or_else(|_| panic!("Invalid port {}", p)))
+        .unwrap_or(8000)
+      ;
+      serve(port);
+    },
+    ["lsp"] => run_lsp(),
+    ["schema", kind @ ..] if kind.len() <= 1 => {
+      let schema = file_schema(kind.first().copied().unwrap_or("bookkeeping"));
+      println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+    },
+    ["fmt", rest @ ..] => {
+      let check = rest.contains(&"--check");
+      let mut files: Vec<std::path::PathBuf> = rest.iter()
+        .filter(|a| **a != "--check")
+        .map(std::path::PathBuf::from)
+        .collect()
+      ;
+      if files.is_empty() {
+        let raw = io.read_path(std::path::Path::new("bookkeeping.yaml"));
+        check_version(&raw);
+        let parsed: Bookkeeping = serde_yaml::from_str(&raw)
+          .expect("Invalid format at bookkeeping.yaml")
+        ;
+        files = std::iter::once(std::path::PathBuf::from("bookkeeping.yaml"))
+          .chain(transaction_files(&parsed))
+          .collect()
+        ;
+      }
+      let mut unformatted = false;
+      for path in files {
+        let raw = io.read_path(&path);
+        let formatted = if path.file_name() == Some("bookkeeping.yaml".as_ref()) {
+          format_bookkeeping(&raw)
+        } else {
+          format_transactions(&raw)
+        };
+        let formatted = formatted
+          .unwrap_or_else(|e| panic!("Failed to format {}: {}", path.display(), e))
+        ;
+        if formatted == raw { continue; }
+        unformatted = true;
+        if check {
+          println!("Would reformat {}", path.display());
+        }
+        else {
+          std::fs::write(&path, formatted)
+            .unwrap_or_else(|e| panic!("Failed to write {}: {}", path.display(), e))
+          ;
+          println!("Formatted {}", path.display());
+        }
+      }
+      if check && unformatted { std::process::exit(1); }
+    },
+    ["migrate"] => {
+      for message in migrate(&mut io) {
+        println!("{}", message);
+      }
+    },
+    _ => {
+      eprint!("{}\nTemplates:\n{}", USAGE, template_list());
+      std::process::exit(1);
+    },
   }
 }
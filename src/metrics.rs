@@ -0,0 +1,259 @@
+//! Key figures calculated from the sums, such as savings rate.
+//!
+//! Each metric is a formula like `(income + expense) / income` over account
+//! sums, accounts and account types, using numbers, `+ - * /` and parentheses.
+//! Names are looked up in that order. Metrics are evaluated for each grouping
+//! and the total, division by zero giving no value.
+
+use std::fmt;
+use serde::{
+  Serialize,
+  Deserialize,
+};
+use schemars::JsonSchema;
+use rust_decimal::Decimal;
+
+use crate::types::*;
+use crate::calculate::*;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+  Number(Decimal),
+  Name(String),
+  Neg(Box<Expr>),
+  Add(Box<Expr>, Box<Expr>),
+  Sub(Box<Expr>, Box<Expr>),
+  Mul(Box<Expr>, Box<Expr>),
+  Div(Box<Expr>, Box<Expr>),
+}
+impl Expr {
+  fn names<'a>(&'a self, out: &mut Vec<&'a str>) {
+    match self {
+      Expr::Number(_) => {},
+      Expr::Name(n) => out.push(n),
+      Expr::Neg(e) => e.names(out),
+      Expr::Add(a, b) | Expr::Sub(a, b) | Expr::Mul(a, b) | Expr::Div(a, b) => {
+        a.names(out);
+        b.names(out);
+      },
+    }
+  }
+  fn eval(&self, lookup: &impl Fn(&str) -> Decimal) -> Option<Decimal> {
+    Some(match self {
+      Expr::Number(x) => *x,
+      Expr::Name(n) => lookup(n),
+      Expr::Neg(e) => -e.eval(lookup)?,
+      Expr::Add(a, b) => a.eval(lookup)? + b.eval(lookup)?,
+      Expr::Sub(a, b) => a.eval(lookup)? - b.eval(lookup)?,
+      Expr::Mul(a, b) => a.eval(lookup)?.checked_mul(b.eval(lookup)?)?,
+      Expr::Div(a, b) => a.eval(lookup)?.checked_div(b.eval(lookup)?)?,
+    })
+  }
+}
+
+// A recursive descent parser over the characters of the formula
+struct Parser<'a> {
+  rest: &'a str,
+}
+impl Parser<'_> {
+  fn peek(&mut self) -> Option<char> {
+    self.rest = self.rest.trim_start();
+    self.rest.chars().next()
+  }
+  fn take(&mut self, pred: impl Fn(char) -> bool) -> &str {
+    let end = self.rest.find(|c| !pred(c)).unwrap_or(self.rest.len());
+    let (taken, rest) = self.rest.split_at(end);
+    self.rest = rest;
+    taken
+  }
+  // sum = product (("+" | "-") product)*
+  fn sum(&mut self) -> Result<Expr, String> {
+    let mut expr = self.product()?;
+    loop {
+      match self.peek() {
+        Some('+') => { self.rest = &self.rest[1..]; expr = Expr::Add(Box::new(expr), Box::new(self.product()?)); },
+        Some('-') => { self.rest = &self.rest[1..]; expr = Expr::Sub(Box::new(expr), Box::new(self.product()?)); },
+        _ => return Ok(expr),
+      }
+    }
+  }
+  // product = factor (("*" | "/") factor)*
+  fn product(&mut self) -> Result<Expr, String> {
+    let mut expr = self.factor()?;
+    loop {
+      match self.peek() {
+        Some('*') => { self.rest = &self.rest[1..]; expr = Expr::Mul(Box::new(expr), Box::new(self.factor()?)); },
+        Some('/') => { self.rest = &self.rest[1..]; expr = Expr::Div(Box::new(expr), Box::new(self.factor()?)); },
+        _ => return Ok(expr),
+      }
+    }
+  }
+  // factor = "-" factor | "(" sum ")" | number | name
+  fn factor(&mut self) -> Result<Expr, String> {
+    match self.peek() {
+      Some('-') => {
+        self.rest = &self.rest[1..];
+        Ok(Expr::Neg(Box::new(self.factor()?)))
+      },
+      Some('(') => {
+        self.rest = &self.rest[1..];
+        let expr = self.sum()?;
+        if self.peek() != Some(')') { return Err("missing )".to_owned()); }
+        self.rest = &self.rest[1..];
+        Ok(expr)
+      },
+      Some(c) if c.is_ascii_digit() => {
+        let raw = self.take(|c| c.is_ascii_digit() || c == '.');
+        raw.parse().map(Expr::Number).map_err(|_| format!("invalid number {}", raw))
+      },
+      Some(c) if c.is_alphabetic() || c == '_' => {
+        let name = self.take(|c| c.is_alphanumeric() || c == '_' || c == ':');
+        Ok(Expr::Name(name.to_owned()))
+      },
+      Some(c) => Err(format!("unexpected {}", c)),
+      None => Err("unexpected end".to_owned()),
+    }
+  }
+}
+
+/// A parsed formula, written as a string.
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub struct Formula {
+  pub raw: String,
+  pub expr: Expr,
+}
+impl TryFrom<String> for Formula {
+  type Error = String;
+  fn try_from(raw: String) -> Result<Self, Self::Error> {
+    let mut parser = Parser{ rest: &raw };
+    let expr = parser.sum()
+      .and_then(|e| match parser.peek() {
+        None => Ok(e),
+        Some(c) => Err(format!("unexpected {}", c)),
+      })
+      .map_err(|e| format!("Invalid formula {}: {}", raw, e))?
+    ;
+    Ok(Formula{ raw, expr })
+  }
+}
+impl From<Formula> for String {
+  fn from(formula: Formula) -> String {
+    formula.raw
+  }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricFormat {
+  #[default]
+  Amount,
+  // The value times 100 with a % sign
+  Percent,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Metric {
+  pub formula: Formula,
+  #[serde(default)]
+  pub format: MetricFormat,
+}
+
+fn account_type(name: &str) -> Option<AccountType> {
+  match name {
+    "income" => Some(AccountType::Income),
+    "debtor" => Some(AccountType::Debtor),
+    "asset" => Some(AccountType::Asset),
+    "creditor" => Some(AccountType::Creditor),
+    "expense" => Some(AccountType::Expense),
+    "yearly_result" => Some(AccountType::YearlyResult),
+    _ => None,
+  }
+}
+
+/// Panic if a metric uses a name that isn't an account sum, account or type.
+pub fn validate_metrics(metrics: &[(String, Metric)], book: &RealBookkeeping) {
+  for (name, metric) in metrics {
+    let mut names = Vec::new();
+    metric.formula.expr.names(&mut names);
+    for used in names {
+      if !book.account_sums.iter().any(|(s, _)| s == used)
+        && !book.accounts.contains(used)
+        && account_type(used).is_none()
+      {
+        panic!("Metric {} uses {}, which is neither an account sum, account nor account type.", name, used);
+      }
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct MetricValue {
+  pub name: String,
+  // None if the formula divides by zero
+  pub value: Option<Decimal>,
+  pub format: MetricFormat,
+}
+impl fmt::Display for MetricValue {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match (self.value, self.format) {
+      (None, _) => write!(f, "n/a"),
+      (Some(v), MetricFormat::Amount) => write!(f, "{:.2}", v),
+      (Some(v), MetricFormat::Percent) => write!(f, "{:.1}%", v * Decimal::ONE_HUNDRED),
+    }
+  }
+}
+
+/// Evaluate the metrics over the sums of one grouping (or the total).
+pub fn evaluate_metrics(metrics: &[(String, Metric)], gs: &SummedGrouping) -> Vec<MetricValue> {
+  let lookup = |name: &str| -> Decimal {
+    if let Some((_, sum, _)) = gs.account_sums.iter().find(|(s, _, _)| s == name) {
+      return *sum;
+    }
+    if let Some(account) = gs.account_types.iter()
+      .flat_map(|(_, _, accounts)| accounts)
+      .find(|a| a.name == name)
+    {
+      return account.sum;
+    }
+    gs.account_types.iter()
+      .find(|(t, _, _)| Some(*t) == account_type(name))
+      .map(|(_, sum, _)| *sum)
+      .unwrap_or_default()
+  };
+  metrics.iter().map(|(name, metric)| MetricValue{
+    name: name.clone(),
+    value: metric.formula.expr.eval(&lookup),
+    format: metric.format,
+  }).collect()
+}
+
+// Metric names and formatted values, with order preserved
+#[derive(Debug, Serialize)]
+pub struct FormattedMetrics(
+  #[serde(with = "tuple_vec_map")]
+  pub Vec<(String, String)>,
+);
+
+#[derive(Debug, Serialize)]
+pub struct MetricsReport {
+  pub name: String,
+  pub total: FormattedMetrics,
+  #[serde(with = "tuple_vec_map")]
+  pub groupings: Vec<(String, FormattedMetrics)>,
+}
+
+fn formatted(metrics: &[MetricValue]) -> FormattedMetrics {
+  FormattedMetrics(metrics.iter().map(|m| (m.name.clone(), m.to_string())).collect())
+}
+
+/// The formatted metrics for each grouping and the total.
+pub fn metrics_report(summary: &SummedBookkeeping) -> MetricsReport {
+  MetricsReport{
+    name: summary.name.clone(),
+    total: formatted(&summary.total.metrics),
+    groupings: summary.groupings.iter()
+      .map(|(name, gs)| (name.clone(), formatted(&gs.metrics)))
+      .collect(),
+  }
+}
@@ -0,0 +1,297 @@
+//! Versions of the file format and upgrading older files to the current one.
+//!
+//! Files without a version field are either:
+//! - version 0, the original format with `accounts: {name: type}`, the
+//!   `initial_value` type and `!Inlined`/`!Path` tags on the groupings
+//! - version 1 from before the version field was added, which only needs it
+//!   added
+//!
+//! Adding the version field is done on the text, keeping comments. Older
+//! files are converted through their parsed form, which loses the comments,
+//! so a backup is saved next to every file rewritten.
+
+use std::path::{
+  Path,
+  PathBuf,
+};
+use crate::file_io::FileIO;
+use serde_yaml::{
+  Mapping,
+  Value,
+  value::{
+    Tag,
+    TaggedValue,
+  },
+};
+
+/// The version of the file format this build reads.
+pub const VERSION: u64 = 1;
+
+// Guess the version of a file without a version field
+fn detect_version(root: &Value) -> u64 {
+  if let Some(version) = root.get("version") {
+    return version.as_u64().expect("The version in bookkeeping.yaml must be a number");
+  }
+  let legacy_accounts = root.get("accounts")
+    .and_then(|a| a.as_mapping())
+    .is_some_and(|a| a.values().all(|t| t.is_string()) && !a.is_empty())
+  ;
+  let legacy_groupings = root.get("groupings")
+    .and_then(|g| g.as_sequence())
+    .is_some_and(|g| g.iter().any(|g| matches!(g, Value::Tagged(_))))
+  ;
+  if legacy_accounts || legacy_groupings { 0 } else { 1 }
+}
+
+/// Panic with a helpful message unless the root file is of the current
+/// version, instead of whatever error parsing an old format would give.
+pub fn check_version(raw: &str) {
+  // Let the parsing report invalid yaml
+  let root: Value = match serde_yaml::from_str(raw) {
+    Ok(root) => root,
+    Err(_) => return,
+  };
+  let detected = detect_version(&root);
+  if root.get("version").is_none() {
+    let format = if detected == 0 { "the original format" } else { "the format from before versioning" };
+    panic!("bookkeeping.yaml has no version field, it looks like {}. Run `bookkeep migrate` to upgrade it to version {}.",
+      format, VERSION,
+    );
+  }
+  if detected > VERSION {
+    panic!("bookkeeping.yaml is version {}, but this build of bookkeep only reads up to version {}. Upgrade bookkeep.",
+      detected, VERSION,
+    );
+  }
+  if detected < VERSION {
+    panic!("bookkeeping.yaml is version {}, run `bookkeep migrate` to upgrade it to version {}.", detected, VERSION);
+  }
+}
+
+// Save the original next to the file and write the new contents
+fn rewrite(io: &mut impl FileIO, path: &Path, contents: &str) -> PathBuf {
+  let mut backup = path.as_os_str().to_owned();
+  backup.push(".bak");
+  let backup = PathBuf::from(backup);
+  let original = io.read_path(path);
+  io.write_path(&backup, &original)
+    .unwrap_or_else(|e| panic!("Failed to back up {}: {}", path.display(), e))
+  ;
+  io.write_path(path, contents).unwrap_or_else(|e| panic!("{}", e));
+  backup
+}
+
+// Put the version field before the first key, after any leading comments
+fn add_version(raw: &str) -> String {
+  let mut out = String::new();
+  let mut added = false;
+  for line in raw.split_inclusive('\n') {
+    let trimmed = line.trim();
+    if !added && !trimmed.is_empty() && !trimmed.starts_with('#') && !trimmed.starts_with("---") {
+      out.push_str(&format!("version: {}\n", VERSION));
+      added = true;
+    }
+    out.push_str(line);
+  }
+  if !added { out.push_str(&format!("version: {}\n", VERSION)); }
+  out
+}
+
+// The original format listed accounts as name: type
+fn migrate_accounts(accounts: &Mapping) -> Mapping {
+  let mut by_type = Mapping::new();
+  for (name, account_type) in accounts {
+    let account_type = match account_type.as_str() {
+      Some("initial_value") => "yearly_result",
+      Some(t) => t,
+      None => panic!("Account {:?} has an invalid type", name),
+    };
+    let names = by_type.entry(Value::from(account_type))
+      .or_insert_with(|| Value::Sequence(Vec::new()))
+    ;
+    names.as_sequence_mut().unwrap().push(name.clone());
+  }
+  by_type
+}
+
+// The original format tagged the groupings, with the grouping's name inside
+// the file for `!Path` groupings. Backups of rewritten files are added.
+fn migrate_grouping(io: &mut impl FileIO, grouping: &Value, backups: &mut Vec<PathBuf>) -> Value {
+  let tagged = match grouping {
+    Value::Tagged(tagged) => tagged,
+    // Already in the new layout
+    _ => return grouping.clone(),
+  };
+  let mut new = Mapping::new();
+  if tagged.tag == "Inlined" {
+    let name = tagged.value.get("name").expect("Inlined grouping without a name").clone();
+    let transactions = tagged.value.get("transactions").cloned().unwrap_or(Value::Sequence(Vec::new()));
+    new.insert("name".into(), name);
+    new.insert("transactions".into(), Value::Tagged(Box::new(TaggedValue{
+      tag: Tag::new("Inlined"),
+      value: transactions,
+    })));
+  }
+  else if tagged.tag == "Path" {
+    let path = tagged.value.as_str().expect("The !Path of a grouping must be a string");
+    let file: Value = serde_yaml::from_str(&io.read_path(Path::new(path)))
+      .unwrap_or_else(|_| panic!("Invalid format at {}", path))
+    ;
+    let name = file.get("name").unwrap_or_else(|| panic!("Grouping at {} has no name", path)).clone();
+    let transactions = file.get("transactions").cloned().unwrap_or(Value::Sequence(Vec::new()));
+    backups.push(rewrite(io, Path::new(path), &serde_yaml::to_string(&transactions).unwrap()));
+    new.insert("name".into(), name);
+    new.insert("transactions".into(), Value::Tagged(Box::new(TaggedValue{
+      tag: Tag::new("Paths"),
+      value: Value::Sequence(vec![Value::from(path)]),
+    })));
+  }
+  else {
+    panic!("Unknown grouping tag {} in the original format", tagged.tag);
+  }
+  Value::Mapping(new)
+}
+
+fn migrate_from_0(io: &mut impl FileIO, root: Value, backups: &mut Vec<PathBuf>) -> Value {
+  let root = match root {
+    Value::Mapping(m) => m,
+    _ => panic!("bookkeeping.yaml must be a mapping"),
+  };
+  let mut new = Mapping::new();
+  new.insert("version".into(), Value::from(VERSION));
+  for (key, value) in root {
+    let value = match key.as_str() {
+      Some("accounts") => Value::Mapping(migrate_accounts(
+        value.as_mapping().expect("accounts must be a mapping")
+      )),
+      Some("groupings") => Value::Sequence(
+        value.as_sequence().expect("groupings must be a list")
+          .iter().map(|g| migrate_grouping(io, g, backups)).collect()
+      ),
+      _ => value,
+    };
+    new.insert(key, value);
+  }
+  new.entry("account_sums".into()).or_insert_with(|| Value::Mapping(Mapping::new()));
+  Value::Mapping(new)
+}
+
+/// Upgrade ./bookkeeping.yaml (and the files it includes, if needed) to the
+/// current version. Returns a description of what was done.
+pub fn migrate(io: &mut impl FileIO) -> Vec<String> {
+  let path = Path::new("bookkeeping.yaml");
+  let raw = io.read_path(path);
+  let root: Value = serde_yaml::from_str(&raw)
+    .unwrap_or_else(|e| panic!("Invalid yaml at bookkeeping.yaml: {}", e))
+  ;
+  let version = detect_version(&root);
+  let mut backups = Vec::new();
+  match version {
+    v if v > VERSION => panic!("bookkeeping.yaml is version {}, newer than this build of bookkeep reads.", v),
+    v if v == VERSION && root.get("version").is_some() => {
+      return vec![format!("bookkeeping.yaml is already version {}.", VERSION)];
+    },
+    // Only the version field is missing
+    1 => {
+      io.write_path(path, &add_version(&raw)).unwrap_or_else(|e| panic!("{}", e));
+      return vec![format!("Added the version field to bookkeeping.yaml, it is version {}.", VERSION)];
+    },
+    _ => {
+      let new = migrate_from_0(io, root, &mut backups);
+      backups.push(rewrite(io, path, &serde_yaml::to_string(&new).unwrap()));
+    },
+  }
+  let mut messages = vec![format!("Upgraded bookkeeping.yaml from version {} to {}.", version, VERSION)];
+  if !backups.is_empty() {
+    messages.push("Comments could not be kept in the rewritten files, the originals are saved as:".to_owned());
+    messages.extend(backups.iter().map(|b| format!("  {}", b.display())));
+  }
+  messages
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::file_io::FakeFileIO;
+  use crate::{
+    calculate,
+    load,
+  };
+
+  // The layout of the example before versioning, trimmed
+  const BOOK: &str = "\
+# The whole bookkeeping is configured from this file.
+name: 2023
+accounts:
+  initial_money: initial_value
+  money: asset
+  salary: income
+  electronics: expense
+groupings:
+- !Inlined
+  name: Start of year
+  transactions:
+  - name: Initial money
+    date: 2023-01-01
+    transfers:
+      initial_money: -45002
+      money: 45002
+- !Path january.yaml
+";
+
+  const JANUARY: &str = "\
+name: January
+transactions:
+- name: January salary
+  date: 2023-01-25
+  transfers:
+    salary: -25034
+    money: 25034
+- name: Buy computer
+  date: 2023-01-25
+  transfers:
+    electronics: 25000
+    money: -25000
+";
+
+  fn io(book: &str) -> FakeFileIO {
+    FakeFileIO{ files: [
+      (PathBuf::from("bookkeeping.yaml"), book.to_owned()),
+      (PathBuf::from("january.yaml"), JANUARY.to_owned()),
+    ].into() }
+  }
+
+  #[test]
+  fn from_0() {
+    let mut io = io(BOOK);
+    let messages = migrate(&mut io);
+    assert_eq!(messages[0], "Upgraded bookkeeping.yaml from version 0 to 1.");
+    assert_eq!(io.files[Path::new("bookkeeping.yaml.bak")], BOOK);
+    assert_eq!(io.files[Path::new("january.yaml.bak")], JANUARY);
+    let summary = calculate(load(&mut io));
+    assert_eq!(summary.groupings.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["Start of year", "January"]);
+    // Migrating again does nothing
+    assert_eq!(migrate(&mut io), ["bookkeeping.yaml is already version 1."]);
+  }
+
+  #[test]
+  fn version_added() {
+    let book = "# A comment\nname: test\naccounts:\n  asset: [money]\naccount_sums: {}\ngroupings: []\n";
+    let mut io = io(book);
+    migrate(&mut io);
+    assert_eq!(io.files[Path::new("bookkeeping.yaml")], book.replace("# A comment\n", "# A comment\nversion: 1\n"));
+    load(&mut io);
+  }
+
+  #[test]
+  #[should_panic(expected = "bookkeeping.yaml is version 2, but this build of bookkeep only reads up to version 1")]
+  fn newer_version() {
+    check_version("version: 2\nname: test\n");
+  }
+
+  #[test]
+  #[should_panic(expected = "it looks like the original format")]
+  fn unversioned() {
+    check_version(BOOK);
+  }
+}
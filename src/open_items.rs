@@ -0,0 +1,209 @@
+//! Open item tracking for debtor and creditor accounts.
+//!
+//! A transaction with a `reference` opens an item (an invoice) on the debtor
+//! and creditor accounts it transfers to, if the reference has a due date.
+//! Later transactions with the same reference id on the same account settle
+//! it. Single transfers can be given references of their own under
+//! `references`, by account like the VAT codes, so one payment can settle
+//! several items or a transaction can book items for several counterparties.
+//! Whatever remains is an open item, which the aging report sorts by how long
+//! it is overdue.
+
+use std::collections::BTreeMap;
+use serde::{
+  Serialize,
+  Deserialize,
+};
+use schemars::JsonSchema;
+use rust_decimal::Decimal;
+use time::Date;
+
+use crate::types::*;
+use crate::schema::Day;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Reference {
+  // Invoice number or similar, unique per account
+  pub id: String,
+  // Only given when opening the item
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  #[schemars(with = "Option<Day>")]
+  pub due: Option<Date>,
+  // Who owes or is owed, defaults to the account name
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub counterparty: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct OpenItem {
+  pub account: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub account_number: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub account_description: Option<String>,
+  pub id: String,
+  pub date: Date,
+  pub due: Date,
+  pub counterparty: String,
+  // The remaining amount, as booked on the account
+  pub amount: Decimal,
+}
+
+/// All items with a remaining amount as of the given date.
+pub fn open_items(data: &RealBookkeeping, as_of: Date) -> Vec<OpenItem> {
+  let mut items = BTreeMap::<(String, String), OpenItem>::new();
+  let mut transactions: Vec<&RealTransaction> = data.groupings.iter()
+    .flat_map(|g| &g.transactions)
+    .filter(|t| t.date <= as_of)
+    .collect()
+  ;
+  transactions.sort_by_key(|t| t.date);
+  for transaction in transactions {
+    for (i, (account, amount)) in transaction.transfers.iter().enumerate() {
+      let reference = match transaction.reference(i) {
+        Some(r) => r,
+        None => continue,
+      };
+      if !matches!(data.account_type(account), Some(AccountType::Debtor | AccountType::Creditor)) {
+        continue;
+      }
+      // The VAT on an invoice is owed to the state, not the counterparty
+      if data.vat.as_ref().is_some_and(|v| v.contains(account)) {
+        continue;
+      }
+      let key = (account.clone(), reference.id.clone());
+      if let Some(item) = items.get_mut(&key) {
+        item.amount += amount;
+        continue;
+      }
+      let due = reference.due.unwrap_or_else(|| panic!(
+        "Transaction {} settles item {} on {} which isn't opened (opening needs a due date).",
+        transaction.name, reference.id, account,
+      ));
+      let info = data.account_info(account);
+      items.insert(key, OpenItem{
+        account: account.clone(),
+        account_number: info.and_then(|i| i.number),
+        account_description: info.and_then(|i| i.description.clone()),
+        id: reference.id.clone(),
+        date: transaction.date,
+        due,
+        counterparty: reference.counterparty.clone().unwrap_or_else(|| account.clone()),
+        amount: *amount,
+      });
+    }
+  }
+  items.into_values().filter(|item| !item.amount.is_zero()).collect()
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct Aging {
+  pub counterparty: String,
+  pub current: Decimal,
+  pub days_1_30: Decimal,
+  pub days_31_60: Decimal,
+  pub days_61_90: Decimal,
+  pub over_90: Decimal,
+  pub total: Decimal,
+  pub items: Vec<OpenItem>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AgingReport {
+  pub as_of: Date,
+  // Owed to us, on debtor accounts
+  pub receivable: Vec<Aging>,
+  // Owed by us, on creditor accounts (shown positive)
+  pub payable: Vec<Aging>,
+}
+
+fn age(items: Vec<OpenItem>, as_of: Date, sign: Decimal) -> Vec<Aging> {
+  let mut per_counterparty = BTreeMap::<String, Aging>::new();
+  for item in items {
+    let aging = per_counterparty.entry(item.counterparty.clone())
+      .or_insert_with(|| Aging{ counterparty: item.counterparty.clone(), ..Default::default() })
+    ;
+    let amount = item.amount * sign;
+    let bucket = match (as_of - item.due).whole_days() {
+      ..=0 => &mut aging.current,
+      1..=30 => &mut aging.days_1_30,
+      31..=60 => &mut aging.days_31_60,
+      61..=90 => &mut aging.days_61_90,
+      _ => &mut aging.over_90,
+    };
+    *bucket += amount;
+    aging.total += amount;
+    aging.items.push(item);
+  }
+  per_counterparty.into_values().collect()
+}
+
+/// Open items per counterparty, by days overdue as of the given date.
+pub fn aging_report(data: &RealBookkeeping, as_of: Date) -> AgingReport {
+  let (receivable, payable) = open_items(data, as_of).into_iter()
+    .partition(|item| data.account_type(&item.account) == Some(AccountType::Debtor))
+  ;
+  AgingReport{
+    as_of,
+    receivable: age(receivable, as_of, Decimal::ONE),
+    payable: age(payable, as_of, Decimal::NEGATIVE_ONE),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::file_io::DummyFileIO;
+
+  const BOOK: &str = "
+version: 1
+name: test
+accounts:
+  asset: [money]
+  debtor: [customers]
+  income: [sales]
+account_sums: {}
+groupings:
+- name: Q1
+  period: {start: 2023-01-01, end: 2023-03-31}
+  transactions: !Inlined
+  - name: invoices
+    date: 2023-01-10
+    transfers:
+      customers: 100
+      customers: 50
+      sales: -150
+    references:
+      customers: {id: '1', due: 2023-02-10, counterparty: A}
+      customers: {id: '2', due: 2023-02-10, counterparty: B}
+  - name: payment
+    date: 2023-02-01
+    transfers:
+      money: 130
+      customers: -100
+      customers: -30
+    references:
+      customers: {id: '1'}
+      customers: {id: '2'}
+";
+
+  fn d(s: &str) -> Decimal {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn payment_settles_several_items() {
+    let book: Bookkeeping = serde_yaml::from_str(BOOK).unwrap();
+    let real = book.realize(&mut DummyFileIO{});
+    let as_of = Date::from_calendar_date(2023, time::Month::March, 1).unwrap();
+    let items = open_items(&real, as_of);
+    // Invoice 1 is paid in full and 2 in part
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].id, "2");
+    assert_eq!(items[0].amount, d("20"));
+    let report = aging_report(&real, as_of);
+    assert_eq!(report.receivable.len(), 1);
+    assert_eq!(report.receivable[0].counterparty, "B");
+    assert_eq!(report.receivable[0].days_1_30, d("20"));
+  }
+}
@@ -0,0 +1,330 @@
+//! Printable PDF reports, for archiving the bookkeeping.
+//!
+//! The journal, general ledger, balance sheet and income statement are laid
+//! out as rows of monospaced text, so they need no fonts but those every PDF
+//! reader has. Each page has a header with the bookkeeping's name, the period
+//! and its page number, and running totals are carried forward to the next
+//! page.
+
+use printpdf::{
+  BuiltinFont,
+  IndirectFontRef,
+  Line,
+  Mm,
+  PdfDocument,
+  Point,
+};
+use rust_decimal::Decimal;
+
+use crate::types::*;
+use crate::calculate::*;
+use crate::html::accounts_of;
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 15.0;
+const FONT_SIZE: f32 = 8.0;
+// Courier glyphs are 0.6 em wide, in mm at the font size
+const CHAR_WIDTH: f32 = FONT_SIZE * 0.6 * 25.4 / 72.0;
+const LINE_HEIGHT: f32 = 3.8;
+// The characters that fit on a line, and the lines below the page header
+const LINE_CHARS: usize = 104;
+const BODY_LINES: usize = 66;
+
+/// The reports, by the names they are asked for with.
+pub const PDF_REPORTS: [&str; 4] = ["journal", "ledger", "balance-sheet", "income-statement"];
+
+struct Column {
+  header: &'static str,
+  width: usize,
+  right: bool,
+}
+const fn left(header: &'static str, width: usize) -> Column {
+  Column{ header, width, right: false }
+}
+const fn right(header: &'static str, width: usize) -> Column {
+  Column{ header, width, right: true }
+}
+
+struct Row {
+  cells: Vec<String>,
+  bold: bool,
+  // The running totals after this row, to carry to the next page if it
+  // breaks here
+  carry: Option<Vec<String>>,
+}
+impl Row {
+  fn new(cells: Vec<String>) -> Self {
+    Row{ cells, bold: false, carry: None }
+  }
+  fn bold(cells: Vec<String>) -> Self {
+    Row{ cells, bold: true, carry: None }
+  }
+  fn blank() -> Self {
+    Row::new(Vec::new())
+  }
+  fn carrying(mut self, carry: Vec<String>) -> Self {
+    self.carry = Some(carry);
+    self
+  }
+}
+
+struct Report {
+  title: &'static str,
+  columns: Vec<Column>,
+  // The column "Carried forward" and "Brought forward" are written in
+  label: usize,
+  rows: Vec<Row>,
+}
+impl Report {
+  fn line(&self, cells: &[String]) -> String {
+    self.columns.iter().enumerate()
+      .map(|(i, c)| fit(cells.get(i).map(String::as_str).unwrap_or(""), c.width, c.right))
+      .collect::<Vec<_>>()
+      .join(" ")
+      .trim_end()
+      .to_owned()
+  }
+  fn carried(&self, carry: &[String], label: &str) -> String {
+    let mut cells = carry.to_vec();
+    cells.resize(self.columns.len(), String::new());
+    cells[self.label] = label.to_owned();
+    self.line(&cells)
+  }
+  // The lines of each page, bold or not
+  fn pages(&self) -> Vec<Vec<(String, bool)>> {
+    let mut pages = Vec::new();
+    let mut page = Vec::new();
+    let mut carry: Option<&Vec<String>> = None;
+    for row in &self.rows {
+      // The last line is kept for carrying forward
+      if page.len() == BODY_LINES - 1 {
+        if let Some(carry) = carry {
+          page.push((self.carried(carry, "Carried forward"), true));
+        }
+        pages.push(std::mem::take(&mut page));
+        if let Some(carry) = carry {
+          page.push((self.carried(carry, "Brought forward"), true));
+        }
+      }
+      page.push((self.line(&row.cells), row.bold));
+      carry = row.carry.as_ref();
+    }
+    if !page.is_empty() || pages.is_empty() {
+      pages.push(page);
+    }
+    pages
+  }
+}
+
+// The text cut or padded to the width
+fn fit(text: &str, width: usize, right: bool) -> String {
+  let text: String = text.chars().take(width).collect();
+  if right { format!("{:>width$}", text) } else { format!("{:<width$}", text) }
+}
+
+fn amount(amount: Decimal) -> String {
+  format!("{:.2}", amount)
+}
+
+fn label(real: &RealBookkeeping, account: &str) -> String {
+  match real.account_info(account).and_then(|i| i.number) {
+    Some(number) => format!("{} {}", number, account),
+    None => account.to_owned(),
+  }
+}
+
+// Every transaction in date order, numbered, with its transfers as debit and
+// credit
+fn journal(real: &RealBookkeeping) -> Report {
+  let mut transactions: Vec<&RealTransaction> = real.groupings.iter()
+    .flat_map(|g| &g.transactions)
+    .collect()
+  ;
+  transactions.sort_by_key(|t| t.date);
+  let mut rows = Vec::new();
+  let (mut debit, mut credit) = (Decimal::ZERO, Decimal::ZERO);
+  let totals = |debit, credit| vec![String::new(), String::new(), String::new(), amount(debit), amount(credit)];
+  for (i, t) in transactions.iter().enumerate() {
+    let name = match &t.generated_by {
+      Some(by) => format!("{} [{}]", t.name, by),
+      None => t.name.clone(),
+    };
+    rows.push(Row::bold(vec![(i + 1).to_string(), t.date.to_string(), name]).carrying(totals(debit, credit)));
+    for (account, a) in &t.transfers {
+      let (d, c) = if a.is_sign_negative() { (String::new(), amount(-a)) } else { (amount(*a), String::new()) };
+      if a.is_sign_negative() { credit -= a; } else { debit += a; }
+      rows.push(Row::new(vec![String::new(), String::new(), format!("  {}", label(real, account)), d, c])
+        .carrying(totals(debit, credit))
+      );
+    }
+  }
+  rows.push(Row::bold(vec![String::new(), String::new(), "Total".to_owned(), amount(debit), amount(credit)]));
+  Report{
+    title: "Journal",
+    columns: vec![right("No", 5), left("Date", 10), left("Transaction", 52), right("Debit", 16), right("Credit", 16)],
+    label: 2,
+    rows,
+  }
+}
+
+// The transfers of every account over all groupings, with its balance
+fn ledger(summary: &SummedBookkeeping) -> Report {
+  let mut rows = Vec::new();
+  for account in summary.total.account_types.iter().flat_map(|(_, _, accounts)| accounts) {
+    rows.push(Row::bold(vec![String::new(), account.label()]));
+    let (mut debit, mut credit) = (Decimal::ZERO, Decimal::ZERO);
+    for t in &account.transfers {
+      let (d, c) = if t.amount.is_sign_negative() { (String::new(), amount(-t.amount)) } else { (amount(t.amount), String::new()) };
+      if t.amount.is_sign_negative() { credit -= t.amount; } else { debit += t.amount; }
+      rows.push(Row::new(vec![t.date.to_string(), t.name.clone(), d, c, amount(t.resulting_balance)])
+        .carrying(vec![String::new(), String::new(), amount(debit), amount(credit), amount(t.resulting_balance)])
+      );
+    }
+    rows.push(Row::bold(vec![String::new(), format!("Total {}", account.label()), amount(debit), amount(credit), amount(account.sum)]));
+    rows.push(Row::blank());
+  }
+  Report{
+    title: "General ledger",
+    columns: vec![left("Date", 10), left("Transaction", 46), right("Debit", 15), right("Credit", 15), right("Balance", 15)],
+    label: 1,
+    rows,
+  }
+}
+
+// A section of accounts with their sums times the factor and any extra
+// amounts, returning its total
+fn section(
+  rows: &mut Vec<Row>,
+  title: &str,
+  accounts: &[&SummedAccount],
+  factor: Decimal,
+  extra: &[(&str, Decimal)],
+) -> Decimal {
+  rows.push(Row::bold(vec![title.to_owned()]));
+  let mut total = Decimal::ZERO;
+  for account in accounts {
+    total += account.sum * factor;
+    rows.push(Row::new(vec![format!("  {}", account.label()), amount(account.sum * factor)]));
+  }
+  for (name, a) in extra {
+    total += a;
+    rows.push(Row::new(vec![format!("  {}", name), amount(*a)]));
+  }
+  rows.push(Row::bold(vec![format!("Total {}", title.to_lowercase()), amount(total)]));
+  rows.push(Row::blank());
+  total
+}
+
+// As the balance sheet page of the html module
+fn balance_sheet(summary: &SummedBookkeeping) -> Report {
+  let gs = &summary.total;
+  let mut rows = Vec::new();
+  let assets = section(&mut rows, "Assets", &accounts_of(gs, &[AccountType::Asset, AccountType::Debtor]), Decimal::ONE, &[]);
+  let liabilities = section(&mut rows, "Liabilities", &accounts_of(gs, &[AccountType::Creditor]), -Decimal::ONE, &[]);
+  let result: Decimal = -accounts_of(gs, &[AccountType::Income, AccountType::Expense]).iter().map(|a| a.sum).sum::<Decimal>();
+  let equity = section(&mut rows, "Equity", &accounts_of(gs, &[AccountType::YearlyResult]), -Decimal::ONE, &[("Result", result)]);
+  rows.push(Row::bold(vec!["Total liabilities and equity".to_owned(), amount(liabilities + equity)]));
+  if assets != liabilities + equity {
+    rows.push(Row::new(vec![format!("The assets differ from the liabilities and equity by {}", amount(assets - liabilities - equity))]));
+  }
+  Report{
+    title: "Balance sheet",
+    columns: vec![left("Account", 60), right("Amount", 16)],
+    label: 0,
+    rows,
+  }
+}
+
+// As the income statement page of the html module, for all groupings
+fn income_statement(summary: &SummedBookkeeping) -> Report {
+  let gs = &summary.total;
+  let mut rows = Vec::new();
+  let income = section(&mut rows, "Income", &accounts_of(gs, &[AccountType::Income]), -Decimal::ONE, &[]);
+  let expenses = section(&mut rows, "Expenses", &accounts_of(gs, &[AccountType::Expense]), Decimal::ONE, &[]);
+  rows.push(Row::bold(vec!["Result".to_owned(), amount(income - expenses)]));
+  Report{
+    title: "Income statement",
+    columns: vec![left("Account", 60), right("Amount", 16)],
+    label: 0,
+    rows,
+  }
+}
+
+fn pdf(e: printpdf::Error) -> String {
+  format!("PDF error: {}", e)
+}
+
+/// Write the named reports (all if none) into a PDF at the path, each
+/// starting on a new page. Returns the number of pages.
+pub fn write_pdf(
+  real: &RealBookkeeping,
+  summary: &SummedBookkeeping,
+  names: &[&str],
+  path: &std::path::Path,
+) -> Result<usize, String> {
+  let names = if names.is_empty() { &PDF_REPORTS[..] } else { names };
+  let reports = names.iter()
+    .map(|name| match *name {
+      "journal" => Ok(journal(real)),
+      "ledger" => Ok(ledger(summary)),
+      "balance-sheet" => Ok(balance_sheet(summary)),
+      "income-statement" => Ok(income_statement(summary)),
+      x => Err(format!("Unknown report {}, expected one of {}", x, PDF_REPORTS.join(", "))),
+    })
+    .collect::<Result<Vec<Report>, String>>()?
+  ;
+  // The fiscal period the groupings declare, or the transaction dates if
+  // none of them has a period
+  let periods = real.groupings.iter().filter_map(|g| g.period);
+  let dates = real.groupings.iter().flat_map(|g| &g.transactions).map(|t| t.date);
+  let period = match (periods.clone().map(|p| p.start).min(), periods.map(|p| p.end).max()) {
+    (Some(start), Some(end)) => format!("Period {} to {}", start, end),
+    _ => match (dates.clone().min(), dates.max()) {
+      (Some(start), Some(end)) => format!("Transactions {} to {}", start, end),
+      _ => "No transactions".to_owned(),
+    },
+  };
+  let pages: Vec<(&Report, Vec<(String, bool)>)> = reports.iter()
+    .flat_map(|r| r.pages().into_iter().map(move |p| (r, p)))
+    .collect()
+  ;
+
+  let (doc, first_page, first_layer) = PdfDocument::new(&summary.name, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Report");
+  let regular = doc.add_builtin_font(BuiltinFont::Courier).map_err(pdf)?;
+  let bold = doc.add_builtin_font(BuiltinFont::CourierBold).map_err(pdf)?;
+  for (i, (report, lines)) in pages.iter().enumerate() {
+    let (page, layer) = match i {
+      0 => (first_page, first_layer),
+      _ => doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Report"),
+    };
+    let layer = doc.get_page(page).get_layer(layer);
+    let mut y = PAGE_HEIGHT - MARGIN;
+    let text = |line: &str, font: &IndirectFontRef, y: &mut f32| {
+      layer.use_text(line, FONT_SIZE, Mm(MARGIN), Mm(*y), font);
+      *y -= LINE_HEIGHT;
+    };
+    let number = format!("Page {} of {}", i + 1, pages.len());
+    let title = fit(&format!("{}: {}", summary.name, report.title), LINE_CHARS - number.len() - 1, false);
+    text(&format!("{} {}", title, number), &bold, &mut y);
+    text(&period, &regular, &mut y);
+    y -= LINE_HEIGHT;
+    let headers: Vec<String> = report.columns.iter().map(|c| c.header.to_owned()).collect();
+    text(&report.line(&headers), &bold, &mut y);
+    let rule = y + LINE_HEIGHT - 1.0;
+    layer.set_outline_thickness(0.5);
+    layer.add_line(Line{
+      points: vec![
+        (Point::new(Mm(MARGIN), Mm(rule)), false),
+        (Point::new(Mm(MARGIN + LINE_CHARS as f32 * CHAR_WIDTH), Mm(rule)), false),
+      ],
+      is_closed: false,
+    });
+    for (line, is_bold) in lines {
+      text(line, if *is_bold { &bold } else { &regular }, &mut y);
+    }
+  }
+  let bytes = doc.save_to_bytes().map_err(pdf)?;
+  std::fs::write(path, bytes).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+  Ok(pages.len())
+}
@@ -0,0 +1,451 @@
+//! A small query language for finding transfers.
+//!
+//! A query is a list of terms that a transfer must all match, such as
+//! `account:electronics amount>1000 date:2023-Q1`. The terms are:
+//! - `account:NAME`, where the name may use `*` wildcards like `home:*`
+//! - `type:TYPE`, the type of the account, such as `expense`
+//! - `sum:NAME`, the account is included in the account_sums entry
+//! - `date:FROM..TO`, where either end may be left out and each is a year,
+//!   quarter (`2023-Q2`), month (`2023-01`) or date. Without `..` only that
+//!   year, quarter, month or date.
+//! - `amount>N`, or `>=`, `<`, `<=` and `=`, comparing the signed amount
+//! - `name:REGEX`, the transaction name matches the regular expression
+//! - `comment:KEY` or `comment:KEY=REGEX`, the transaction has the comment
+//!
+//! Terms are negated with `not` or a leading `-`, alternatives are separated
+//! with `or` and parentheses group. Values with spaces go in double quotes.
+
+use std::collections::{
+  BTreeMap,
+  BTreeSet,
+};
+use std::fmt::Write;
+use serde::Serialize;
+use rust_decimal::Decimal;
+use regex::Regex;
+use time::{
+  Date,
+  Month,
+};
+
+use crate::types::*;
+use crate::sums::matches_pattern;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Comparison {
+  Less,
+  LessOrEqual,
+  Equal,
+  GreaterOrEqual,
+  Greater,
+}
+
+#[derive(Debug)]
+pub enum Query {
+  Account(String),
+  Type(AccountType),
+  // The accounts the sum includes
+  Sum(BTreeSet<String>),
+  Date(Option<Date>, Option<Date>),
+  Amount(Comparison, Decimal),
+  Name(Regex),
+  Comment(String, Option<Regex>),
+  Not(Box<Query>),
+  All(Vec<Query>),
+  Any(Vec<Query>),
+}
+
+/// A transfer found by a query, with what it is matched against.
+#[derive(Debug, Serialize, Clone)]
+pub struct QueryRow {
+  pub grouping: String,
+  pub date: Date,
+  pub transaction: String,
+  pub account: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub account_number: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub account_description: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub account_type: Option<AccountType>,
+  pub amount: Decimal,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub comments: BTreeMap<String, String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub generated_by: Option<String>,
+}
+
+impl QueryRow {
+  /// The account prefixed with its number, if any.
+  pub fn account_label(&self) -> String {
+    match self.account_number {
+      Some(number) => format!("{} {}", number, self.account),
+      None => self.account.clone(),
+    }
+  }
+}
+
+impl Query {
+  pub fn matches(&self, row: &QueryRow) -> bool {
+    match self {
+      Query::Account(pattern) => matches_pattern(pattern, &row.account),
+      Query::Type(t) => row.account_type == Some(*t),
+      Query::Sum(accounts) => accounts.contains(&row.account),
+      Query::Date(from, to) => from.is_none_or(|d| d <= row.date) && to.is_none_or(|d| row.date <= d),
+      Query::Amount(comparison, x) => match comparison {
+        Comparison::Less => row.amount < *x,
+        Comparison::LessOrEqual => row.amount <= *x,
+        Comparison::Equal => row.amount == *x,
+        Comparison::GreaterOrEqual => row.amount >= *x,
+        Comparison::Greater => row.amount > *x,
+      },
+      Query::Name(regex) => regex.is_match(&row.transaction),
+      Query::Comment(key, value) => match (row.comments.get(key), value) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(v), Some(regex)) => regex.is_match(v),
+      },
+      Query::Not(q) => !q.matches(row),
+      Query::All(qs) => qs.iter().all(|q| q.matches(row)),
+      Query::Any(qs) => qs.iter().any(|q| q.matches(row)),
+    }
+  }
+}
+
+// Split into terms, parentheses and keywords. Quotes only keep spaces and
+// parentheses inside a term, they aren't part of it.
+fn tokenize(raw: &str) -> Result<Vec<String>, String> {
+  let mut tokens = Vec::new();
+  let mut token = String::new();
+  let mut quoted = false;
+  for c in raw.chars() {
+    match c {
+      '"' => quoted = !quoted,
+      c if quoted => token.push(c),
+      '(' | ')' => {
+        if !token.is_empty() { tokens.push(std::mem::take(&mut token)); }
+        tokens.push(c.to_string());
+      },
+      c if c.is_whitespace() => {
+        if !token.is_empty() { tokens.push(std::mem::take(&mut token)); }
+      },
+      c => token.push(c),
+    }
+  }
+  if quoted { return Err("missing closing \"".to_owned()); }
+  if !token.is_empty() { tokens.push(token); }
+  Ok(tokens)
+}
+
+// The first and last day of a year, quarter, month or single date
+fn period(raw: &str) -> Option<(Date, Date)> {
+  let month_end = |year: i32, month: Month| Date::from_calendar_date(year, month, month.length(year)).ok();
+  let parts: Vec<&str> = raw.split('-').collect();
+  match parts.as_slice() {
+    [year] => {
+      let year = year.parse().ok()?;
+      Some((Date::from_calendar_date(year, Month::January, 1).ok()?, month_end(year, Month::December)?))
+    },
+    [year, quarter] if quarter.starts_with(['Q', 'q']) => {
+      let year = year.parse().ok()?;
+      let quarter: u8 = quarter[1..].parse().ok().filter(|q| (1..=4).contains(q))?;
+      let first = Month::try_from(quarter * 3 - 2).ok()?;
+      Some((Date::from_calendar_date(year, first, 1).ok()?, month_end(year, first.next().next())?))
+    },
+    [year, month] => {
+      let year = year.parse().ok()?;
+      let month = Month::try_from(month.parse::<u8>().ok()?).ok()?;
+      Some((Date::from_calendar_date(year, month, 1).ok()?, month_end(year, month)?))
+    },
+    _ => {
+      let date: Date = serde_yaml::from_str(raw).ok()?;
+      Some((date, date))
+    },
+  }
+}
+
+fn dates(raw: &str) -> Result<Query, String> {
+  let invalid = |x: &str| format!("invalid date {}, expected a year, quarter (2023-Q1), month or date", x);
+  let bound = |x: &str| if x.is_empty() { Ok(None) } else { period(x).map(Some).ok_or_else(|| invalid(x)) };
+  match raw.split_once("..") {
+    Some((from, to)) => Ok(Query::Date(bound(from)?.map(|p| p.0), bound(to)?.map(|p| p.1))),
+    None => {
+      let (from, to) = period(raw).ok_or_else(|| invalid(raw))?;
+      Ok(Query::Date(Some(from), Some(to)))
+    },
+  }
+}
+
+fn regex(raw: &str) -> Result<Regex, String> {
+  Regex::new(raw).map_err(|e| format!("invalid regex {}: {}", raw, e))
+}
+
+// A recursive descent parser over the tokens, checking names against the
+// bookkeeping
+struct Parser<'a> {
+  tokens: Vec<String>,
+  next: usize,
+  book: &'a RealBookkeeping,
+}
+impl Parser<'_> {
+  fn peek(&self) -> Option<&str> {
+    self.tokens.get(self.next).map(|t| t.as_str())
+  }
+  // any = all ("or" all)*
+  fn any(&mut self) -> Result<Query, String> {
+    let mut alternatives = vec![self.all()?];
+    while self.peek() == Some("or") {
+      self.next += 1;
+      alternatives.push(self.all()?);
+    }
+    Ok(if alternatives.len() == 1 { alternatives.pop().unwrap() } else { Query::Any(alternatives) })
+  }
+  // all = not not*
+  fn all(&mut self) -> Result<Query, String> {
+    let mut terms = vec![self.not()?];
+    while !matches!(self.peek(), None | Some("or") | Some(")")) {
+      terms.push(self.not()?);
+    }
+    Ok(if terms.len() == 1 { terms.pop().unwrap() } else { Query::All(terms) })
+  }
+  // not = ("not" | "-") not | "(" any ")" | term
+  fn not(&mut self) -> Result<Query, String> {
+    let token = self.peek().ok_or("unexpected end")?.to_owned();
+    self.next += 1;
+    match token.as_str() {
+      "not" | "-" => Ok(Query::Not(Box::new(self.not()?))),
+      "(" => {
+        let query = self.any()?;
+        if self.peek() != Some(")") { return Err("missing )".to_owned()); }
+        self.next += 1;
+        Ok(query)
+      },
+      "or" | ")" => Err(format!("unexpected {}", token)),
+      _ => match token.strip_prefix('-') {
+        Some(term) => Ok(Query::Not(Box::new(self.term(term)?))),
+        None => self.term(&token),
+      },
+    }
+  }
+  fn term(&self, raw: &str) -> Result<Query, String> {
+    if let Some(rest) = raw.strip_prefix("amount") {
+      let (comparison, number) = [
+        (">=", Comparison::GreaterOrEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">", Comparison::Greater),
+        ("<", Comparison::Less),
+        ("=", Comparison::Equal),
+      ].into_iter()
+        .find_map(|(op, c)| rest.strip_prefix(op).map(|n| (c, n)))
+        .ok_or_else(|| format!("expected a comparison after amount in {}", raw))?
+      ;
+      let number = number.parse().map_err(|_| format!("invalid amount {}", number))?;
+      return Ok(Query::Amount(comparison, number));
+    }
+    let (key, value) = raw.split_once(':')
+      .ok_or_else(|| format!("unknown term {}, expected a key such as account:NAME", raw))?
+    ;
+    match key {
+      "account" => {
+        if !self.book.accounts.iter().any(|a| matches_pattern(value, a)) {
+          return Err(format!("no account matches {}", value));
+        }
+        Ok(Query::Account(value.to_owned()))
+      },
+      "type" => serde_yaml::from_str(value)
+        .map(Query::Type)
+        .map_err(|_| format!("unknown account type {}", value)),
+      "sum" => self.book.account_sums.iter()
+        .find(|(name, _)| name == value)
+        .map(|(_, accounts)| Query::Sum(accounts.iter().map(|(a, _)| a.clone()).collect()))
+        .ok_or_else(|| format!("no account sum named {}", value)),
+      "date" => dates(value),
+      "name" => Ok(Query::Name(regex(value)?)),
+      "comment" => Ok(match value.split_once('=') {
+        Some((key, value)) => Query::Comment(key.to_owned(), Some(regex(value)?)),
+        None => Query::Comment(value.to_owned(), None),
+      }),
+      _ => Err(format!("unknown term {}, expected account, type, sum, date, amount, name or comment", key)),
+    }
+  }
+}
+
+/// Parse the query, checking that the accounts and sums it names exist.
+pub fn parse_query(raw: &str, book: &RealBookkeeping) -> Result<Query, String> {
+  let parse = || {
+    let mut parser = Parser{ tokens: tokenize(raw)?, next: 0, book };
+    if parser.tokens.is_empty() { return Ok(Query::All(Vec::new())); }
+    let query = parser.any()?;
+    match parser.peek() {
+      None => Ok(query),
+      Some(t) => Err(format!("unexpected {}", t)),
+    }
+  };
+  parse().map_err(|e| format!("Invalid query {}: {}", raw, e))
+}
+
+/// The transfers matching the query, by date.
+pub fn query_rows(book: &RealBookkeeping, query: &Query) -> Vec<QueryRow> {
+  let mut rows: Vec<QueryRow> = book.groupings.iter()
+    .flat_map(|g| g.transactions.iter().map(move |t| (g, t)))
+    .flat_map(|(g, t)| t.transfers.iter().map(move |(account, amount)| QueryRow{
+      grouping: g.name.clone(),
+      date: t.date,
+      transaction: t.name.clone(),
+      account: account.clone(),
+      account_number: book.account_info(account).and_then(|i| i.number),
+      account_description: book.account_info(account).and_then(|i| i.description.clone()),
+      account_type: book.account_type(account),
+      amount: *amount,
+      comments: t.comments.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+      generated_by: t.generated_by.clone(),
+    }))
+    .filter(|row| query.matches(row))
+    .collect()
+  ;
+  // Stable, so transfers keep the order they are written in
+  rows.sort_by_key(|r| r.date);
+  rows
+}
+
+/// Aligned columns with the total of the amounts last.
+pub fn render_table(rows: &[QueryRow]) -> String {
+  let total: Decimal = rows.iter().map(|r| r.amount).sum();
+  let cells: Vec<[String; 5]> = rows.iter()
+    .map(|r| [r.date.to_string(), r.grouping.clone(), r.transaction.clone(), r.account_label(), r.amount.to_string()])
+    .collect()
+  ;
+  let header = ["Date", "Grouping", "Transaction", "Account", "Amount"].map(String::from);
+  let footer = ["Total".to_owned(), String::new(), String::new(), String::new(), total.to_string()];
+  let lines: Vec<&[String; 5]> = std::iter::once(&header).chain(&cells).chain([&footer]).collect();
+  let widths: Vec<usize> = (0..5)
+    .map(|i| lines.iter().map(|l| l[i].chars().count()).max().unwrap_or(0))
+    .collect()
+  ;
+  let mut out = String::new();
+  for line in lines {
+    let text = format!("{:<w0$}  {:<w1$}  {:<w2$}  {:<w3$}  {:>w4$}",
+      line[0], line[1], line[2], line[3], line[4],
+      w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3], w4 = widths[4],
+    );
+    writeln!(out, "{}", text.trim_end()).unwrap();
+  }
+  out
+}
+
+fn csv_field(raw: &str) -> String {
+  if raw.contains([',', '"', '\n']) {
+    format!("\"{}\"", raw.replace('"', "\"\""))
+  } else {
+    raw.to_owned()
+  }
+}
+
+/// One line per transfer, after a header line.
+pub fn render_csv(rows: &[QueryRow]) -> String {
+  let mut out = "date,grouping,transaction,account_number,account,account_description,amount\n".to_owned();
+  for r in rows {
+    writeln!(out, "{},{},{},{},{},{},{}",
+      r.date,
+      csv_field(&r.grouping),
+      csv_field(&r.transaction),
+      r.account_number.map(|n| n.to_string()).unwrap_or_default(),
+      csv_field(&r.account),
+      csv_field(r.account_description.as_deref().unwrap_or_default()),
+      r.amount,
+    ).unwrap();
+  }
+  out
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::file_io::DummyFileIO;
+
+  const BOOK: &str = "
+version: 1
+name: test
+accounts:
+  asset: [money]
+  expense: [food, rent]
+  income: [salary]
+account_sums: {}
+groupings:
+- name: '2023'
+  transactions: !Inlined
+  - name: lunch out
+    date: 2023-01-05
+    transfers:
+      food: 100
+      money: -100
+  - name: rent
+    date: 2023-02-01
+    transfers:
+      rent: 5000
+      money: -5000
+  - name: salary
+    date: 2023-04-25
+    transfers:
+      salary: -20000
+      money: 20000
+";
+
+  fn date(year: i32, month: Month, day: u8) -> Date {
+    Date::from_calendar_date(year, month, day).unwrap()
+  }
+
+  // The transaction and account of each matching transfer
+  fn matching(raw: &str) -> Vec<(String, String)> {
+    let book: Bookkeeping = serde_yaml::from_str(BOOK).unwrap();
+    let real = book.realize(&mut DummyFileIO{});
+    let query = parse_query(raw, &real).unwrap();
+    query_rows(&real, &query).into_iter().map(|r| (r.transaction, r.account)).collect()
+  }
+
+  fn rows(rows: &[(&str, &str)]) -> Vec<(String, String)> {
+    rows.iter().map(|(t, a)| (t.to_string(), a.to_string())).collect()
+  }
+
+  #[test]
+  fn tokens() {
+    assert_eq!(tokenize("name:\"lunch (out)\" (a or -b)").unwrap(), vec![
+      "name:lunch (out)", "(", "a", "or", "-b", ")",
+    ]);
+    assert!(tokenize("name:\"lunch").is_err());
+  }
+
+  #[test]
+  fn periods() {
+    assert_eq!(period("2023-Q1"), Some((date(2023, Month::January, 1), date(2023, Month::March, 31))));
+    assert_eq!(period("2024-02"), Some((date(2024, Month::February, 1), date(2024, Month::February, 29))));
+    assert_eq!(period("2023"), Some((date(2023, Month::January, 1), date(2023, Month::December, 31))));
+    assert_eq!(period("2023-05-17"), Some((date(2023, Month::May, 17), date(2023, Month::May, 17))));
+    assert_eq!(period("2023-Q5"), None);
+  }
+
+  #[test]
+  fn queries() {
+    assert_eq!(matching(""), matching("date:..2023-12-31"));
+    assert_eq!(matching("date:2023-Q1 -account:money"), rows(&[("lunch out", "food"), ("rent", "rent")]));
+    assert_eq!(matching("date:2023-02.."), rows(&[
+      ("rent", "rent"), ("rent", "money"), ("salary", "salary"), ("salary", "money"),
+    ]));
+    // "or" binds looser than the implicit "and"
+    assert_eq!(matching("account:money amount<0 or type:income"), rows(&[
+      ("lunch out", "money"), ("rent", "money"), ("salary", "salary"),
+    ]));
+    assert_eq!(matching("(account:food or account:rent) not date:2023-02"), rows(&[("lunch out", "food")]));
+    assert_eq!(matching("- (type:asset or amount>=5000)"), rows(&[("lunch out", "food"), ("salary", "salary")]));
+    assert_eq!(matching("name:\"^lunch out$\" account:*o*"), rows(&[("lunch out", "food"), ("lunch out", "money")]));
+  }
+
+  #[test]
+  fn errors() {
+    let book: Bookkeeping = serde_yaml::from_str(BOOK).unwrap();
+    let real = book.realize(&mut DummyFileIO{});
+    for raw in ["account:nope", "(account:food", "account:food)", "date:2023-Q5", "or account:food", "amount~5", "food"] {
+      assert!(parse_query(raw, &real).is_err(), "{} should be invalid", raw);
+    }
+  }
+}
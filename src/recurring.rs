@@ -0,0 +1,298 @@
+//! Recurring transactions, such as rent and salaries.
+//!
+//! A recurring definition is expanded into an ordinary transaction for every
+//! date its schedule hits within the period of a grouping. Groupings without a
+//! period get no recurring transactions.
+
+use std::collections::BTreeMap;
+use serde::{
+  Serialize,
+  Deserialize,
+};
+use schemars::JsonSchema;
+use rust_decimal::Decimal;
+use time::{
+  Date,
+  Duration,
+  Month,
+  Weekday,
+};
+
+use crate::types::*;
+use crate::vat::VatCode;
+use crate::schema::{
+  Day,
+  MonthName,
+  WeekdayName,
+  yaml_tags,
+};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case")]
+#[schemars(transform = yaml_tags)]
+pub enum Schedule {
+  /// On the given day every month, or the last day of shorter months
+  Monthly(u8),
+  /// On the given weekday every week
+  Weekly(#[schemars(with = "WeekdayName")] Weekday),
+  /// On the given date every year, or the last day of a shorter month
+  Yearly{ #[schemars(with = "MonthName")] month: Month, day: u8 },
+  /// On the last day of every month
+  EndOfMonth,
+}
+impl Schedule {
+  pub fn matches(&self, date: Date) -> bool {
+    let last_day = date.month().length(date.year());
+    match self {
+      Schedule::Monthly(day) => date.day() == (*day).min(last_day),
+      Schedule::Weekly(weekday) => date.weekday() == *weekday,
+      Schedule::Yearly{ month, day } => date.month() == *month && date.day() == (*day).min(last_day),
+      Schedule::EndOfMonth => date.day() == last_day,
+    }
+  }
+}
+
+/// How to move a date that falls on a weekend. (Holidays aren't considered.)
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum BusinessDay {
+  // The friday before
+  Preceding,
+  // The monday after
+  Following,
+}
+impl BusinessDay {
+  pub fn adjust(&self, mut date: Date) -> Date {
+    while matches!(date.weekday(), Weekday::Saturday | Weekday::Sunday) {
+      date = match self {
+        BusinessDay::Preceding => date - Duration::days(1),
+        BusinessDay::Following => date + Duration::days(1),
+      };
+    }
+    date
+  }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Recurring {
+  pub name: String,
+  pub schedule: Schedule,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub business_day: Option<BusinessDay>,
+  // Limits when the transaction recurs, in addition to the grouping periods
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  #[schemars(with = "Option<Day>")]
+  pub start: Option<Date>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  #[schemars(with = "Option<Day>")]
+  pub end: Option<Date>,
+  #[serde(with = "tuple_vec_map")]
+  #[schemars(with = "BTreeMap<String, Decimal>")]
+  pub transfers: Vec<(String, Decimal)>,
+  #[serde(default, with = "tuple_vec_map", skip_serializing_if = "Vec::is_empty")]
+  #[schemars(with = "BTreeMap<String, VatCode>")]
+  pub vat: Vec<(String, VatCode)>,
+  // Changed amounts for the transaction on the given (booked) date
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  #[schemars(with = "BTreeMap<Day, BTreeMap<String, Decimal>>")]
+  pub overrides: BTreeMap<Date, BTreeMap<String, Decimal>>,
+}
+impl Recurring {
+  /// The dates the transaction is booked on within the given period.
+  pub fn dates(&self, period: &Period) -> Vec<Date> {
+    // Look a week outside the period, since weekend adjustment can move
+    // dates into it
+    let mut date = period.start - Duration::weeks(1);
+    let mut dates = Vec::new();
+    while date <= period.end + Duration::weeks(1) {
+      if self.schedule.matches(date) {
+        let booked = match self.business_day {
+          Some(b) => b.adjust(date),
+          None => date,
+        };
+        if period.contains(booked)
+          && self.start.is_none_or(|start| start <= booked)
+          && self.end.is_none_or(|end| booked <= end)
+        {
+          dates.push(booked);
+        }
+      }
+      date += Duration::days(1);
+    }
+    dates
+  }
+
+  fn transaction(&self, date: Date) -> Transaction {
+    let mut transfers = self.transfers.clone();
+    if let Some(overrides) = self.overrides.get(&date) {
+      for (account, amount) in overrides {
+        match transfers.iter_mut().find(|(a, _)| a == account) {
+          Some((_, x)) => *x = *amount,
+          None => transfers.push((account.to_owned(), *amount)),
+        }
+      }
+    }
+    Transaction{
+      name: self.name.clone(),
+      date,
+      transfers,
+      vat: self.vat.clone(),
+      accrue: None,
+      reference: None,
+      references: Vec::new(),
+      comments: Default::default(),
+      generated_by: Some(format!("recurring: {}", self.name)),
+      source: None,
+    }
+  }
+}
+
+/// All recurring transactions within the given period, in date order.
+pub fn expand_recurring(recurring: &[Recurring], period: &Period) -> Vec<Transaction> {
+  let mut transactions: Vec<Transaction> = recurring.iter()
+    .flat_map(|r| r.dates(period).into_iter().map(|date| r.transaction(date)))
+    .collect()
+  ;
+  transactions.sort_by_key(|t| t.date);
+  transactions
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn day(year: i32, month: Month, day: u8) -> Date {
+    Date::from_calendar_date(year, month, day).unwrap()
+  }
+
+  fn d(s: &str) -> Decimal {
+    s.parse().unwrap()
+  }
+
+  // January to April 2024, a leap year
+  fn period() -> Period {
+    Period{ start: day(2024, Month::January, 1), end: day(2024, Month::April, 30) }
+  }
+
+  fn recurring(yaml: &str) -> Recurring {
+    serde_yaml::from_str(&format!("name: rent\n{}transfers:\n  rent: 1000\n  money: -1000\n", yaml)).unwrap()
+  }
+
+  fn dates(yaml: &str) -> Vec<Date> {
+    recurring(yaml).dates(&period())
+  }
+
+  #[test]
+  fn monthly() {
+    assert_eq!(dates("schedule: !monthly 15\n"), [
+      day(2024, Month::January, 15),
+      day(2024, Month::February, 15),
+      day(2024, Month::March, 15),
+      day(2024, Month::April, 15),
+    ]);
+    // Shorter months get the last day
+    assert_eq!(dates("schedule: !monthly 31\n"), [
+      day(2024, Month::January, 31),
+      day(2024, Month::February, 29),
+      day(2024, Month::March, 31),
+      day(2024, Month::April, 30),
+    ]);
+  }
+
+  #[test]
+  fn end_of_month() {
+    assert_eq!(dates("schedule: end_of_month\n"), dates("schedule: !monthly 31\n"));
+  }
+
+  #[test]
+  fn weekly() {
+    let dates = dates("schedule: !weekly Friday\nend: 2024-01-31\n");
+    assert_eq!(dates, [
+      day(2024, Month::January, 5),
+      day(2024, Month::January, 12),
+      day(2024, Month::January, 19),
+      day(2024, Month::January, 26),
+    ]);
+  }
+
+  #[test]
+  fn yearly() {
+    let r = recurring("schedule: !yearly { month: February, day: 30 }\n");
+    assert_eq!(r.dates(&period()), [day(2024, Month::February, 29)]);
+    let period = Period{ start: day(2023, Month::January, 1), end: day(2023, Month::December, 31) };
+    assert_eq!(r.dates(&period), [day(2023, Month::February, 28)]);
+  }
+
+  #[test]
+  fn start_and_end() {
+    assert_eq!(dates("schedule: !monthly 1\nstart: 2024-01-02\nend: 2024-03-01\n"), [
+      day(2024, Month::February, 1),
+      day(2024, Month::March, 1),
+    ]);
+  }
+
+  #[test]
+  fn business_day() {
+    // March 31st 2024 is a sunday
+    let march = Period{ start: day(2024, Month::March, 1), end: day(2024, Month::April, 30) };
+    let dates = |business_day: &str| {
+      recurring(&format!("schedule: end_of_month\nbusiness_day: {}\n", business_day)).dates(&march)[0]
+    };
+    assert_eq!(dates("preceding"), day(2024, Month::March, 29));
+    assert_eq!(dates("following"), day(2024, Month::April, 1));
+  }
+
+  #[test]
+  fn moved_across_period() {
+    // December 31st 2023 and June 30th 2024 are sundays, moved into and out of
+    // the period
+    let period = Period{ start: day(2024, Month::January, 1), end: day(2024, Month::June, 30) };
+    let r = recurring("schedule: end_of_month\nbusiness_day: following\n");
+    let dates = r.dates(&period);
+    assert_eq!(dates.first(), Some(&day(2024, Month::January, 1)));
+    assert_eq!(dates.last(), Some(&day(2024, Month::May, 31)));
+    assert_eq!(dates.len(), 6);
+  }
+
+  #[test]
+  fn overrides() {
+    let r = recurring("\
+schedule: !monthly 1
+business_day: following
+overrides:
+  2024-02-01:
+    rent: 1200
+  2024-06-03:
+    rent: 1100
+    fee: 5
+");
+    let period = Period{ start: day(2024, Month::January, 1), end: day(2024, Month::June, 30) };
+    let transactions = expand_recurring(&[r], &period);
+    let transfers = |i: usize| transactions[i].transfers.clone();
+    assert_eq!(transfers(0), [("rent".to_owned(), d("1000")), ("money".to_owned(), d("-1000"))]);
+    assert_eq!(transfers(1), [("rent".to_owned(), d("1200")), ("money".to_owned(), d("-1000"))]);
+    // June 1st is a saturday, the override is for the booked date
+    assert_eq!(transactions[5].date, day(2024, Month::June, 3));
+    assert_eq!(transfers(5), [
+      ("rent".to_owned(), d("1100")),
+      ("money".to_owned(), d("-1000")),
+      ("fee".to_owned(), d("5")),
+    ]);
+    assert_eq!(transactions[5].generated_by.as_deref(), Some("recurring: rent"));
+  }
+
+  #[test]
+  fn sorted_by_date() {
+    let weekly = recurring("schedule: !weekly Monday\nend: 2024-01-31\n");
+    let monthly = recurring("schedule: !monthly 10\nend: 2024-01-31\n");
+    let dates: Vec<Date> = expand_recurring(&[weekly, monthly], &period()).iter().map(|t| t.date).collect();
+    assert_eq!(dates, [
+      day(2024, Month::January, 1),
+      day(2024, Month::January, 8),
+      day(2024, Month::January, 10),
+      day(2024, Month::January, 15),
+      day(2024, Month::January, 22),
+      day(2024, Month::January, 29),
+    ]);
+  }
+}
@@ -0,0 +1,98 @@
+//! JSON Schemas for the file formats, derived from the serde types.
+//!
+//! Enums such as `transactions: !Paths [feb.yaml]` are written with YAML tags,
+//! which JSON Schema can't express. Their variants are instead described by
+//! the value after the tag, which is what an editor told about the tags (like
+//! with the customTags setting of yaml-language-server) validates.
+
+use std::borrow::Cow;
+use schemars::{
+  JsonSchema,
+  Schema,
+  SchemaGenerator,
+  json_schema,
+  schema_for,
+};
+use serde_json::Value;
+
+use crate::types::*;
+use crate::budget::Budget;
+
+/// A date, written as YYYY-MM-DD
+pub struct Day;
+impl JsonSchema for Day {
+  fn inline_schema() -> bool { true }
+  fn schema_name() -> Cow<'static, str> { "Date".into() }
+  fn json_schema(_: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+      "type": "string",
+      "format": "date",
+      "pattern": r"^\d{4}-\d{2}-\d{2}$",
+    })
+  }
+}
+
+/// A month, written by its english name
+pub struct MonthName;
+impl JsonSchema for MonthName {
+  fn schema_name() -> Cow<'static, str> { "Month".into() }
+  fn json_schema(_: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+      "type": "string",
+      "enum": [
+        "January", "February", "March", "April", "May", "June", "July",
+        "August", "September", "October", "November", "December",
+      ],
+    })
+  }
+}
+
+/// A weekday, written by its english name
+pub struct WeekdayName;
+impl JsonSchema for WeekdayName {
+  fn schema_name() -> Cow<'static, str> { "Weekday".into() }
+  fn json_schema(_: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+      "type": "string",
+      "enum": ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"],
+    })
+  }
+}
+
+/// Replace the `{Variant: value}` alternatives of an enum written with YAML
+/// tags by the schema of the value, noting the tag in its description. Unit
+/// variants are written as plain strings and are kept.
+pub fn yaml_tags(schema: &mut Schema) {
+  let variants = match schema.get_mut("oneOf").and_then(Value::as_array_mut) {
+    Some(v) => v,
+    None => return,
+  };
+  for variant in variants {
+    let tag = match variant.get("required").and_then(Value::as_array).map(Vec::as_slice) {
+      Some([Value::String(tag)]) => tag.clone(),
+      _ => continue,
+    };
+    let mut value = match variant.get_mut("properties").and_then(|p| p.get_mut(&tag)) {
+      Some(v) => v.take(),
+      None => continue,
+    };
+    let description = match variant.get("description").and_then(Value::as_str) {
+      Some(d) => format!("Tagged !{}: {}", tag, d),
+      None => format!("Tagged !{}", tag),
+    };
+    if let Some(object) = value.as_object_mut() {
+      object.insert("description".to_owned(), description.into());
+    }
+    *variant = value;
+  }
+}
+
+/// The schema of bookkeeping.yaml, a transaction file or a budget file.
+pub fn file_schema(kind: &str) -> Schema {
+  match kind {
+    "bookkeeping" => schema_for!(Bookkeeping),
+    "transactions" => schema_for!(Vec<Transaction>),
+    "budget" => schema_for!(Budget),
+    x => panic!("Unknown schema {}, expected bookkeeping, transactions or budget", x),
+  }
+}
@@ -0,0 +1,157 @@
+//! A local web UI, serving the pages of the html module on localhost.
+//!
+//! The bookkeeping is loaded again when any of its files has changed since it
+//! was last loaded. Each page asks for the version of the files every second
+//! and reloads itself when it changes, so edits show up without doing anything.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{
+  Hash,
+  Hasher,
+};
+use std::path::PathBuf;
+use tiny_http::{
+  Header,
+  Response,
+  Server,
+};
+
+use super::*;
+
+// The files the bookkeeping is read from, as far as they can be found
+fn files() -> Vec<PathBuf> {
+  let root = PathBuf::from("bookkeeping.yaml");
+  let mut files = vec![root.clone()];
+  let parsed = std::fs::read_to_string(&root).ok()
+    .and_then(|raw| serde_yaml::from_str::<Bookkeeping>(&raw).ok())
+  ;
+  if let Some(book) = parsed {
+    files.extend(book.budget.clone());
+    files.extend(transaction_files(&book));
+  }
+  files
+}
+
+// Changes when any of the files is changed, added or removed
+fn version() -> u64 {
+  let mut hasher = DefaultHasher::new();
+  for path in files() {
+    path.hash(&mut hasher);
+    std::fs::metadata(&path).and_then(|m| m.modified()).ok().hash(&mut hasher);
+  }
+  hasher.finish()
+}
+
+fn encode(raw: &str) -> String {
+  raw.bytes().map(|b| match b {
+    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+    b => format!("%{:02X}", b),
+  }).collect()
+}
+
+fn decode(raw: &str) -> Option<String> {
+  let mut bytes = Vec::new();
+  let mut rest = raw.as_bytes();
+  while let Some((&b, tail)) = rest.split_first() {
+    if b == b'%' {
+      let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+      bytes.push(u8::from_str_radix(hex, 16).ok()?);
+      rest = &tail[2..];
+    } else {
+      bytes.push(b);
+      rest = tail;
+    }
+  }
+  String::from_utf8(bytes).ok()
+}
+
+// Files named in comments aren't served
+struct Served;
+impl Links for Served {
+  fn page(&self, page: &Page) -> String {
+    match page {
+      Page::Overview => "/".to_owned(),
+      Page::Grouping(name) => format!("/grouping/{}", encode(name)),
+      Page::Account(None, account) => format!("/account/{}", encode(account)),
+      Page::Account(Some(name), account) => format!("/grouping/{}/account/{}", encode(name), encode(account)),
+      Page::BalanceSheet => "/balance-sheet".to_owned(),
+      Page::IncomeStatement => "/income-statement".to_owned(),
+    }
+  }
+  fn file(&self, _: &str) -> Option<String> {
+    None
+  }
+}
+
+// The page at the path of the URL, as the grouping and account names it is
+// for if any
+enum Route {
+  Page(Option<String>, Option<String>),
+  BalanceSheet,
+  IncomeStatement,
+}
+fn route(path: &str) -> Option<Route> {
+  let parts: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+  Some(match parts.as_slice() {
+    [""] => Route::Page(None, None),
+    ["grouping", name] => Route::Page(Some(decode(name)?), None),
+    ["account", account] => Route::Page(None, Some(decode(account)?)),
+    ["grouping", name, "account", account] => Route::Page(Some(decode(name)?), Some(decode(account)?)),
+    ["balance-sheet"] => Route::BalanceSheet,
+    ["income-statement"] => Route::IncomeStatement,
+    _ => return None,
+  })
+}
+
+fn html(body: String, status: u16) -> Response<std::io::Cursor<Vec<u8>>> {
+  Response::from_string(body)
+    .with_status_code(status)
+    .with_header(Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap())
+}
+
+/// Serve the bookkeeping in the current directory on the port of localhost,
+/// until killed.
+pub fn serve(port: u16) {
+  let server = Server::http(("127.0.0.1", port))
+    .unwrap_or_else(|e| panic!("Failed to listen on port {}: {}", port, e))
+  ;
+  println!("Serving the bookkeeping on http://127.0.0.1:{}/", port);
+  let mut loaded: Option<(u64, Result<SummedBookkeeping, String>)> = None;
+  for request in server.incoming_requests() {
+    let current = version();
+    if loaded.as_ref().is_none_or(|(v, _)| *v != current) {
+      loaded = Some((current, catch_panic(|| calculate(load(&mut StdFileIO{})))));
+    }
+    let summary = &loaded.as_ref().unwrap().1;
+    let path = request.url().split('?').next().unwrap_or("/").to_owned();
+    if path == "/version" {
+      let _ = request.respond(Response::from_string(current.to_string()));
+      continue;
+    }
+    let reload = format!("<script>setInterval(() => fetch(\"/version\").then(r => r.text())\
+      .then(v => {{ if (v !== \"{}\") location.reload(); }}).catch(() => {{}}), 1000);</script>", current);
+    let rendered = match summary {
+      Err(e) => Err(format!("<h1>The bookkeeping fails to load</h1>\n<pre>{}</pre>\n", escape_html(e))),
+      Ok(summary) => route(&path)
+        .and_then(|route| {
+          let page = match &route {
+            Route::Page(grouping, Some(account)) => Page::Account(grouping.as_deref(), account),
+            Route::Page(Some(grouping), None) => Page::Grouping(grouping),
+            Route::Page(None, None) => Page::Overview,
+            Route::BalanceSheet => Page::BalanceSheet,
+            Route::IncomeStatement => Page::IncomeStatement,
+          };
+          render_page(summary, &page, &Served)
+        })
+        .ok_or_else(|| "<h1>Not found</h1>\n<p><a href=\"/\">Totals</a></p>\n".to_owned()),
+    };
+    let response = match rendered {
+      Ok((title, body)) => html(document(&title, &reload, &body), 200),
+      Err(body) if summary.is_err() => html(document("Failed to load", &reload, &body), 500),
+      Err(body) => html(document("Not found", &reload, &body), 404),
+    };
+    if let Err(e) = request.respond(response) {
+      eprintln!("Failed to respond to {}: {}", path, e);
+    }
+  }
+}
@@ -0,0 +1,101 @@
+//! Writing the pages of the html module into a directory, to be opened or
+//! shared without running anything.
+//!
+//! All pages are in the directory itself and link to each other relatively.
+//! Files named in comments (such as `receipt: receipts/rent.pdf`) that exist
+//! are copied into files/ under the same path and linked there.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::path::{
+  Component,
+  Path,
+};
+
+use crate::calculate::*;
+use crate::html::*;
+
+// The name as part of a file name, with anything but letters, digits and .
+// escaped. Page names join slugs with -, so it is escaped too and different
+// pages never give the same file.
+fn slug(name: &str) -> String {
+  name.bytes().map(|b| match b {
+    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' => (b as char).to_string(),
+    b => format!("_{:02X}", b),
+  }).collect()
+}
+
+struct Site {
+  // The files named in comments that are linked, to copy
+  files: RefCell<BTreeSet<String>>,
+}
+impl Links for Site {
+  fn page(&self, page: &Page) -> String {
+    match page {
+      Page::Overview => "index.html".to_owned(),
+      Page::Grouping(name) => format!("grouping-{}.html", slug(name)),
+      Page::Account(None, account) => format!("account-{}.html", slug(account)),
+      Page::Account(Some(name), account) => format!("grouping-{}-account-{}.html", slug(name), slug(account)),
+      Page::BalanceSheet => "balance-sheet.html".to_owned(),
+      Page::IncomeStatement => "income-statement.html".to_owned(),
+    }
+  }
+  fn file(&self, path: &str) -> Option<String> {
+    let relative = Path::new(path);
+    let within = relative.components().all(|c| matches!(c, Component::Normal(_)));
+    if !within || !relative.is_file() { return None; }
+    self.files.borrow_mut().insert(path.to_owned());
+    Some(format!("files/{}", path))
+  }
+}
+
+fn write(path: &Path, content: &str) -> Result<(), String> {
+  std::fs::write(path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Write every page into the directory, creating it if needed, with the
+/// files named in comments. Returns the number of pages.
+pub fn write_site(summary: &SummedBookkeeping, dir: &Path) -> Result<usize, String> {
+  std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+  let mut pages = vec![Page::Overview, Page::BalanceSheet, Page::IncomeStatement];
+  for (name, gs) in [(None, &summary.total)].into_iter().chain(summary.groupings.iter().map(|(n, g)| (Some(n.as_str()), g))) {
+    if let Some(name) = name {
+      pages.push(Page::Grouping(name));
+    }
+    for account in gs.account_types.iter().flat_map(|(_, _, accounts)| accounts) {
+      pages.push(Page::Account(name, &account.name));
+    }
+  }
+  let site = Site { files: RefCell::new(BTreeSet::new()) };
+  for page in &pages {
+    let (title, body) = render_page(summary, page, &site).expect("Pages of the summary exist");
+    write(&dir.join(site.page(page)), &document(&title, "", &body))?;
+  }
+  for path in site.files.borrow().iter() {
+    let target = dir.join("files").join(path);
+    if let Some(parent) = target.parent() {
+      std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    std::fs::copy(path, &target).map_err(|e| format!("Failed to copy {}: {}", path, e))?;
+  }
+  Ok(pages.len())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn slugs() {
+    assert_eq!(slug("Q1 2023"), "Q1_202023");
+    assert_eq!(slug("a-b.c/d"), "a_2Db.c_2Fd");
+  }
+
+  #[test]
+  fn pages_never_collide() {
+    let site = Site{ files: Default::default() };
+    let account = |grouping: &str, account: &str| site.page(&Page::Account(Some(grouping), account));
+    assert_ne!(account("a", "b-account-x"), account("a-account-b", "x"));
+    assert_ne!(site.page(&Page::Grouping("a-account-b")), account("a", "b"));
+  }
+}
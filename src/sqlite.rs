@@ -0,0 +1,217 @@
+//! Exporting the bookkeeping into a SQLite database, for querying with SQL.
+//!
+//! The database is written from scratch every time, with the rows inserted in
+//! the order of the bookkeeping and ids numbered from 1, so the same
+//! bookkeeping always gives the same database. Amounts are NUMERIC, so SQLite
+//! stores them as integers or reals and they can be compared and summed.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use rusqlite::{
+  Connection,
+  params,
+};
+use rust_decimal::Decimal;
+
+use crate::types::*;
+use crate::calculate::*;
+
+const SCHEMA: &str = "
+PRAGMA foreign_keys = ON;
+CREATE TABLE groupings (
+  id INTEGER PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE
+);
+CREATE TABLE accounts (
+  name TEXT PRIMARY KEY,
+  type TEXT NOT NULL,
+  number INTEGER,
+  description TEXT,
+  currency TEXT,
+  opened TEXT,
+  closed TEXT,
+  hidden INTEGER NOT NULL
+);
+CREATE INDEX accounts_type ON accounts(type);
+CREATE TABLE transactions (
+  id INTEGER PRIMARY KEY,
+  grouping_id INTEGER NOT NULL REFERENCES groupings(id),
+  -- The index of the transaction within its grouping
+  position INTEGER NOT NULL,
+  date TEXT NOT NULL,
+  name TEXT NOT NULL,
+  generated_by TEXT
+);
+CREATE INDEX transactions_grouping ON transactions(grouping_id);
+CREATE INDEX transactions_date ON transactions(date);
+CREATE TABLE transfers (
+  id INTEGER PRIMARY KEY,
+  transaction_id INTEGER NOT NULL REFERENCES transactions(id),
+  account TEXT NOT NULL REFERENCES accounts(name),
+  amount NUMERIC NOT NULL,
+  -- The balance of the account after the transfer, over all groupings
+  balance NUMERIC NOT NULL,
+  reference TEXT,
+  due TEXT,
+  counterparty TEXT
+);
+CREATE INDEX transfers_transaction ON transfers(transaction_id);
+CREATE INDEX transfers_account ON transfers(account);
+CREATE TABLE comments (
+  transaction_id INTEGER NOT NULL REFERENCES transactions(id),
+  key TEXT NOT NULL,
+  value TEXT NOT NULL,
+  PRIMARY KEY (transaction_id, key)
+);
+CREATE TABLE account_sums (
+  name TEXT PRIMARY KEY
+);
+CREATE TABLE account_sum_accounts (
+  sum TEXT NOT NULL REFERENCES account_sums(name),
+  account TEXT NOT NULL REFERENCES accounts(name),
+  factor NUMERIC NOT NULL,
+  PRIMARY KEY (sum, account)
+);
+CREATE INDEX account_sum_accounts_account ON account_sum_accounts(account);
+CREATE TABLE grouping_accounts (
+  grouping_id INTEGER NOT NULL REFERENCES groupings(id),
+  account TEXT NOT NULL REFERENCES accounts(name),
+  sum NUMERIC NOT NULL,
+  PRIMARY KEY (grouping_id, account)
+);
+CREATE INDEX grouping_accounts_account ON grouping_accounts(account);
+CREATE TABLE grouping_account_sums (
+  grouping_id INTEGER NOT NULL REFERENCES groupings(id),
+  sum TEXT NOT NULL REFERENCES account_sums(name),
+  amount NUMERIC NOT NULL,
+  PRIMARY KEY (grouping_id, sum)
+);
+";
+
+fn sql(e: rusqlite::Error) -> String {
+  format!("SQLite error: {}", e)
+}
+
+fn insert(
+  db: &Connection,
+  real: &RealBookkeeping,
+  summary: &SummedBookkeeping,
+) -> Result<(), rusqlite::Error> {
+  // The balance after each transfer, by the id calculate gives it
+  let balances: BTreeMap<&str, Decimal> = summary.total.account_types.iter()
+    .flat_map(|(_, _, accounts)| accounts)
+    .flat_map(|a| &a.transfers)
+    .map(|t| (t.unique_id.as_str(), t.resulting_balance))
+    .collect()
+  ;
+
+  let mut account = db.prepare(
+    "INSERT INTO accounts VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+  )?;
+  for (account_type, names) in &real.account_types {
+    let account_type = serde_yaml::to_string(account_type).unwrap();
+    for name in names {
+      let info = real.account_info(name).cloned().unwrap_or_default();
+      account.execute(params![
+        name,
+        account_type.trim(),
+        info.number,
+        info.description,
+        info.currency,
+        info.opened.map(|d| d.to_string()),
+        info.closed.map(|d| d.to_string()),
+        info.hidden,
+      ])?;
+    }
+  }
+
+  let mut sum = db.prepare("INSERT INTO account_sums VALUES (?1)")?;
+  let mut sum_account = db.prepare("INSERT INTO account_sum_accounts VALUES (?1, ?2, ?3)")?;
+  for (name, accounts) in &real.account_sums {
+    sum.execute(params![name])?;
+    for (account, factor) in accounts {
+      sum_account.execute(params![name, account, factor.to_string()])?;
+    }
+  }
+
+  let mut grouping = db.prepare("INSERT INTO groupings VALUES (?1, ?2)")?;
+  let mut transaction = db.prepare(
+    "INSERT INTO transactions VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+  )?;
+  let mut transfer = db.prepare("INSERT INTO transfers VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)")?;
+  let mut comment = db.prepare("INSERT INTO comments VALUES (?1, ?2, ?3)")?;
+  let mut transaction_id = 0;
+  let mut transfer_id = 0;
+  for (grouping_id, g) in real.groupings.iter().enumerate() {
+    let grouping_id = grouping_id + 1;
+    grouping.execute(params![grouping_id, g.name])?;
+    for t in &g.transactions {
+      transaction_id += 1;
+      transaction.execute(params![
+        transaction_id,
+        grouping_id,
+        t.index,
+        t.date.to_string(),
+        t.name,
+        t.generated_by,
+      ])?;
+      for (i, (account, amount)) in t.transfers.iter().enumerate() {
+        transfer_id += 1;
+        let balance = balances[format!("{}[{}][{}]", g.name, t.index, i).as_str()];
+        let reference = t.reference(i);
+        transfer.execute(params![
+          transfer_id,
+          transaction_id,
+          account,
+          amount.to_string(),
+          balance.to_string(),
+          reference.map(|r| &r.id),
+          reference.and_then(|r| r.due).map(|d| d.to_string()),
+          reference.and_then(|r| r.counterparty.as_ref()),
+        ])?;
+      }
+      let comments: BTreeMap<&String, &String> = t.comments.iter().collect();
+      for (key, value) in comments {
+        comment.execute(params![transaction_id, key, value])?;
+      }
+    }
+  }
+
+  let mut grouping_account = db.prepare("INSERT INTO grouping_accounts VALUES (?1, ?2, ?3)")?;
+  let mut grouping_sum = db.prepare("INSERT INTO grouping_account_sums VALUES (?1, ?2, ?3)")?;
+  for (grouping_id, (_, g)) in summary.groupings.iter().enumerate() {
+    let grouping_id = grouping_id + 1;
+    for account in g.account_types.iter().flat_map(|(_, _, accounts)| accounts) {
+      grouping_account.execute(params![grouping_id, account.name, account.sum.to_string()])?;
+    }
+    for (name, amount, _) in &g.account_sums {
+      grouping_sum.execute(params![grouping_id, name, amount.to_string()])?;
+    }
+  }
+  Ok(())
+}
+
+/// Write the bookkeeping and its sums into a new database at the path,
+/// replacing any file there.
+pub fn export_sqlite(
+  real: &RealBookkeeping,
+  summary: &SummedBookkeeping,
+  path: &Path,
+) -> Result<(), String> {
+  // Written beside it and moved over it, so a failed export leaves the old
+  let mut partial = path.as_os_str().to_owned();
+  partial.push(".partial");
+  let partial = std::path::PathBuf::from(partial);
+  if partial.exists() {
+    std::fs::remove_file(&partial)
+      .map_err(|e| format!("Failed to remove {}: {}", partial.display(), e))?;
+  }
+  let mut db = Connection::open(&partial).map_err(sql)?;
+  db.execute_batch(SCHEMA).map_err(sql)?;
+  let tx = db.transaction().map_err(sql)?;
+  insert(&tx, real, summary).map_err(sql)?;
+  tx.commit().map_err(sql)?;
+  db.close().map_err(|(_, e)| sql(e))?;
+  std::fs::rename(&partial, path)
+    .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
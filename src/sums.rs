@@ -0,0 +1,180 @@
+//! Resolving the account_sums expressions into signed lists of accounts.
+//!
+//! Each entry in an account sum can be:
+//! - an account name, which is added
+//! - the name of another account sum, whose accounts are added
+//! - a pattern with `*` wildcards, adding all accounts matching it
+//!
+//! Any of them can be prefixed with `-` to subtract instead. Unknown names and
+//! sums including themselves are errors.
+
+use std::collections::BTreeSet;
+use rust_decimal::Decimal;
+
+/// Match a name against a pattern where `*` matches any (possibly empty) text.
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+  match pattern.split_once('*') {
+    None => pattern == name,
+    Some((start, rest)) => {
+      let name = match name.strip_prefix(start) {
+        Some(n) => n,
+        None => return false,
+      };
+      // Try every possible length for the part the wildcard matches
+      (0..=name.len())
+        .filter(|i| name.is_char_boundary(*i))
+        .any(|i| matches_pattern(rest, &name[i..]))
+    },
+  }
+}
+
+fn add(out: &mut Vec<(String, Decimal)>, account: &str, factor: Decimal) {
+  match out.iter_mut().find(|(a, _)| a == account) {
+    Some((_, f)) => *f += factor,
+    None => out.push((account.to_owned(), factor)),
+  }
+}
+
+fn resolve(
+  sums: &[(String, Vec<String>)],
+  accounts: &BTreeSet<String>,
+  path: &mut Vec<String>,
+  factor: Decimal,
+  out: &mut Vec<(String, Decimal)>,
+) {
+  let name = path.last().unwrap();
+  let (_, entries) = sums.iter().find(|(s, _)| s == name).unwrap();
+  for entry in entries {
+    let (entry, factor) = match entry.strip_prefix('-') {
+      Some(e) => (e.trim(), -factor),
+      None => (entry.trim(), factor),
+    };
+    if sums.iter().any(|(s, _)| s == entry) {
+      if path.iter().any(|p| p == entry) {
+        panic!("Account sum {} includes itself through {} -> {}.", entry, path.join(" -> "), entry);
+      }
+      path.push(entry.to_owned());
+      resolve(sums, accounts, path, factor, out);
+      path.pop();
+    }
+    else if entry.contains('*') {
+      let mut found = false;
+      for account in accounts.iter().filter(|a| matches_pattern(entry, a)) {
+        add(out, account, factor);
+        found = true;
+      }
+      if !found {
+        panic!("Account sum {} has pattern {} which matches no account.", path.last().unwrap(), entry);
+      }
+    }
+    else if accounts.contains(entry) {
+      add(out, entry, factor);
+    }
+    else {
+      panic!("Account sum {} includes {}, which is neither an account nor a sum.", path.last().unwrap(), entry);
+    }
+  }
+}
+
+/// Resolve every account sum into its accounts with the factor (usually 1 or
+/// -1) to sum them with.
+pub fn resolve_account_sums(
+  sums: &[(String, Vec<String>)],
+  accounts: &BTreeSet<String>,
+) -> Vec<(String, Vec<(String, Decimal)>)> {
+  sums.iter().map(|(name, _)| {
+    if accounts.contains(name) {
+      panic!("Account sum {} has the same name as an account.", name);
+    }
+    let mut out = Vec::new();
+    resolve(sums, accounts, &mut vec![name.clone()], Decimal::ONE, &mut out);
+    (name.clone(), out)
+  }).collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn accounts() -> BTreeSet<String> {
+    ["money", "savings", "home:rent", "home:power", "food", "salary"].iter().map(|a| a.to_string()).collect()
+  }
+
+  fn sums(raw: &[(&str, &[&str])]) -> Vec<(String, Vec<String>)> {
+    raw.iter().map(|(name, entries)| (name.to_string(), entries.iter().map(|e| e.to_string()).collect())).collect()
+  }
+
+  fn factors(raw: &[(&str, i64)]) -> Vec<(String, Decimal)> {
+    raw.iter().map(|(a, f)| (a.to_string(), Decimal::from(*f))).collect()
+  }
+
+  fn resolved(raw: &[(&str, &[&str])], name: &str) -> Vec<(String, Decimal)> {
+    resolve_account_sums(&sums(raw), &accounts()).into_iter()
+      .find(|(n, _)| n == name)
+      .unwrap().1
+  }
+
+  #[test]
+  fn patterns() {
+    assert!(matches_pattern("home:*", "home:rent"));
+    assert!(matches_pattern("*o*", "food"));
+    assert!(matches_pattern("home:*", "home:"));
+    assert!(!matches_pattern("home:*", "food"));
+    assert!(!matches_pattern("home", "home:rent"));
+  }
+
+  #[test]
+  fn pattern_entry() {
+    assert_eq!(resolved(&[("home", &["home:*"])], "home"), factors(&[("home:power", 1), ("home:rent", 1)]));
+  }
+
+  #[test]
+  fn sum_of_sums() {
+    let raw: &[(&str, &[&str])] = &[
+      ("cash", &["money", "savings"]),
+      ("costs", &["home:*", "food"]),
+      ("left", &["cash", "-costs"]),
+    ];
+    assert_eq!(resolved(raw, "left"), factors(&[
+      ("money", 1), ("savings", 1), ("home:power", -1), ("home:rent", -1), ("food", -1),
+    ]));
+  }
+
+  #[test]
+  fn subtracted() {
+    assert_eq!(resolved(&[("net", &["salary", "- food"])], "net"), factors(&[("salary", 1), ("food", -1)]));
+  }
+
+  #[test]
+  fn repeated_entries_add_up() {
+    let raw: &[(&str, &[&str])] = &[
+      ("costs", &["food", "home:*"]),
+      ("twice", &["food", "costs", "-home:rent"]),
+    ];
+    assert_eq!(resolved(raw, "twice"), factors(&[("food", 2), ("home:power", 1), ("home:rent", 0)]));
+  }
+
+  #[test]
+  #[should_panic(expected = "includes itself through a -> b -> a")]
+  fn cycle() {
+    resolve_account_sums(&sums(&[("a", &["money", "b"]), ("b", &["a"])]), &accounts());
+  }
+
+  #[test]
+  #[should_panic(expected = "Account sum a includes itself through a -> a")]
+  fn includes_itself() {
+    resolve_account_sums(&sums(&[("a", &["money", "-a"])]), &accounts());
+  }
+
+  #[test]
+  #[should_panic(expected = "includes nope, which is neither an account nor a sum")]
+  fn unknown_name() {
+    resolve_account_sums(&sums(&[("a", &["money", "nope"])]), &accounts());
+  }
+
+  #[test]
+  #[should_panic(expected = "has pattern car:* which matches no account")]
+  fn pattern_without_match() {
+    resolve_account_sums(&sums(&[("a", &["car:*"])]), &accounts());
+  }
+}
@@ -1,15 +1,24 @@
 use super::*;
 
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use rust_decimal::Decimal;
 use cursive::{
   Cursive,
   CursiveExt,
+  align::HAlign,
   view::{
     Nameable,
+    Resizable,
   },
   views::{
+    Button,
+    Dialog,
+    DummyView,
+    EditView,
     LinearLayout,
-    TextView,
     ScrollView,
+    TextView,
   },
 };
 use cursive_table_view::{
@@ -62,31 +71,149 @@ use cursive_tree_view::{
 // | 2025-04-25    | ...
 // ...
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum AccountColumn {
+  Name,
+  Sum,
+  Budget,
+}
+#[derive(Clone)]
+struct AccountRow {
+  name: String,
+  sum: Decimal,
+  budget: Option<Decimal>,
+}
+impl TableViewItem<AccountColumn> for AccountRow {
+  fn to_column(&self, column: AccountColumn) -> String {
+    match column {
+      AccountColumn::Name => self.name.clone(),
+      AccountColumn::Sum => self.sum.to_string(),
+      AccountColumn::Budget => self.budget.map(|b| b.to_string()).unwrap_or_default(),
+    }
+  }
+  fn cmp(&self, other: &Self, column: AccountColumn) -> std::cmp::Ordering {
+    match column {
+      AccountColumn::Name => self.name.cmp(&other.name),
+      AccountColumn::Sum => self.sum.cmp(&other.sum),
+      AccountColumn::Budget => self.budget.cmp(&other.budget),
+    }
+  }
+}
+
+// One table per account type, with the accounts' total sums and budgets
+fn account_tables(summary: &SummedBookkeeping) -> LinearLayout {
+  let mut tables = LinearLayout::vertical();
+  for (t, sum, accounts) in &summary.total.account_types {
+    let rows: Vec<AccountRow> = accounts.iter().filter(|a| !a.hidden).map(|account| AccountRow{
+      name: account.label(),
+      sum: account.sum,
+      budget: summary.budget.as_ref()
+        .and_then(|b| b.expected_total(summary, &account.name)),
+    }).collect();
+    if rows.is_empty() { continue; }
+    let height = rows.len() + 2;
+    tables.add_child(
+      TableView::<AccountRow, AccountColumn>::new()
+        .column(AccountColumn::Name, format!("{:?}", t), |c| c)
+        .column(AccountColumn::Sum, sum.to_string(), |c| c.align(HAlign::Right))
+        .column(AccountColumn::Budget, "Budget", |c| c.align(HAlign::Right))
+        .items(rows)
+        .fixed_height(height)
+    );
+  }
+  tables
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum QueryColumn {
+  Date,
+  Grouping,
+  Transaction,
+  Account,
+  Amount,
+}
+impl TableViewItem<QueryColumn> for QueryRow {
+  fn to_column(&self, column: QueryColumn) -> String {
+    match column {
+      QueryColumn::Date => self.date.to_string(),
+      QueryColumn::Grouping => self.grouping.clone(),
+      QueryColumn::Transaction => self.transaction.clone(),
+      QueryColumn::Account => self.account_label(),
+      QueryColumn::Amount => self.amount.to_string(),
+    }
+  }
+  fn cmp(&self, other: &Self, column: QueryColumn) -> std::cmp::Ordering {
+    match column {
+      QueryColumn::Date => self.date.cmp(&other.date),
+      QueryColumn::Grouping => self.grouping.cmp(&other.grouping),
+      QueryColumn::Transaction => self.transaction.cmp(&other.transaction),
+      QueryColumn::Account => (self.account_number, &self.account).cmp(&(other.account_number, &other.account)),
+      QueryColumn::Amount => self.amount.cmp(&other.amount),
+    }
+  }
+}
+
+fn account_label(account: &SummedAccount, budget: &impl Fn(&str) -> Option<Decimal>) -> String {
+  match budget(&account.name) {
+    Some(b) => format!("{}: ({}, budget {})", account.label(), account.sum, b),
+    None => format!("{}: ({})", account.label(), account.sum),
+  }
+}
+
+// A row of the detail tree, knowing its transfer to edit it from there
+#[derive(Debug)]
+struct TreeEntry {
+  label: String,
+  transfer: Option<Transfer>,
+}
+impl std::fmt::Display for TreeEntry {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", self.label)
+  }
+}
+impl From<String> for TreeEntry {
+  fn from(label: String) -> Self {
+    TreeEntry{ label, transfer: None }
+  }
+}
+
+fn transfer_entry(transfer: &Transfer) -> TreeEntry {
+  let label = format!("{}, {}: ({} -> {})", transfer.name, transfer.date, transfer.amount, transfer.resulting_balance);
+  TreeEntry{
+    label: match &transfer.generated_by {
+      Some(by) => format!("{} [{}]", label, by),
+      None => label,
+    },
+    transfer: Some(transfer.clone()),
+  }
+}
+
 fn grouping_summary_to_tree_entries(
-  tree: &mut TreeView<String>,
+  tree: &mut TreeView<TreeEntry>,
   gs: &SummedGrouping,
+  budget: impl Fn(&str) -> Option<Decimal>,
   row: usize,
 ) {
   let r = tree.insert_item(
-    format!("Account types"),
+    "Account types".to_string().into(),
     Placement::LastChild,
     row,
   ).expect("The row on which grouping_summary_to_tree_entries is called on must not be collapsed");
   for (t, sum, accounts) in &gs.account_types {
     let inner_r = tree.insert_item(
-      format!("{:?}: ({})", t, sum),
+      format!("{:?}: ({})", t, sum).into(),
       Placement::LastChild,
       r,
     ).unwrap();
-    for account in accounts {
+    for account in accounts.iter().filter(|a| !a.hidden) {
       let innermost_r = tree.insert_item(
-        format!("{}: ({})", account.name, account.sum),
+        account_label(account, &budget).into(),
         Placement::LastChild,
         inner_r,
       ).unwrap();
       for transfer in &account.transfers {
         tree.insert_item(
-          format!("{}, {}: ({} -> {})", transfer.name, transfer.date, transfer.amount, transfer.resulting_balance),
+          transfer_entry(transfer),
           Placement::LastChild,
           innermost_r,
         );
@@ -98,25 +225,30 @@ fn grouping_summary_to_tree_entries(
   tree.set_collapsed(r, true);
 
   let r = tree.insert_item(
-    format!("Account sums"),
+    "Account sums".to_string().into(),
     Placement::After,
     r,
   ).unwrap();
   for (name, sum, accounts) in &gs.account_sums {
     let inner_r = tree.insert_item(
-      format!("{}: ({})", name, sum),
+      match budget(name) {
+        Some(b) => format!("{}: ({}, budget {})", name, sum, b),
+        None => format!("{}: ({})", name, sum),
+      }.into(),
       Placement::LastChild,
       r,
     ).unwrap();
-    for account in accounts {
+    for (factor, account) in accounts.iter().filter(|(_, a)| !a.hidden) {
+      let label = account_label(account, &budget);
+      let label = if *factor == Decimal::ONE { label } else { format!("{} × {}", factor, label) };
       let innermost_r = tree.insert_item(
-        format!("{}: ({})", account.name, account.sum),
+        label.into(),
         Placement::LastChild,
         inner_r,
       ).unwrap();
       for transfer in &account.transfers {
         tree.insert_item(
-          format!("{}, {}: ({} -> {})", transfer.name, transfer.date, transfer.amount, transfer.resulting_balance),
+          transfer_entry(transfer),
           Placement::LastChild,
           innermost_r,
         );
@@ -126,26 +258,88 @@ fn grouping_summary_to_tree_entries(
     tree.set_collapsed(inner_r, true);
   }
   tree.set_collapsed(r, true);
+
+  let r = tree.insert_item(
+    "Account hierarchy".to_string().into(),
+    Placement::After,
+    r,
+  ).unwrap();
+  for node in &gs.account_hierarchy {
+    hierarchy_to_tree_entries(tree, gs, node, &budget, r);
+  }
+  tree.set_collapsed(r, true);
+
+  if !gs.metrics.is_empty() {
+    let r = tree.insert_item(
+      "Metrics".to_string().into(),
+      Placement::After,
+      r,
+    ).unwrap();
+    for metric in &gs.metrics {
+      tree.insert_item(
+        format!("{}: {}", metric.name, metric).into(),
+        Placement::LastChild,
+        r,
+      );
+    }
+    tree.set_collapsed(r, true);
+  }
 }
 
-pub fn run_tui(
-  summary: SummedBookkeeping,
+fn hierarchy_to_tree_entries(
+  tree: &mut TreeView<TreeEntry>,
+  gs: &SummedGrouping,
+  node: &SummedNode,
+  budget: &impl Fn(&str) -> Option<Decimal>,
+  row: usize,
 ) {
-  let mut siv = Cursive::new();
-  siv.add_global_callback('q', |s| s.quit());
+  let account = gs.account_types.iter()
+    .flat_map(|(_, _, accounts)| accounts)
+    .find(|account| account.name == node.name)
+  ;
+  if node.children.is_empty() && account.is_some_and(|a| a.hidden) { return; }
+  let name = match account.and_then(|a| a.number) {
+    Some(number) => format!("{} {}", number, node.name),
+    None => node.name.clone(),
+  };
+  let r = tree.insert_item(
+    match budget(&node.name) {
+      Some(b) => format!("{}: ({}, budget {})", name, node.sum, b),
+      None => format!("{}: ({})", name, node.sum),
+    }.into(),
+    Placement::LastChild,
+    row,
+  ).unwrap();
+  for child in &node.children {
+    hierarchy_to_tree_entries(tree, gs, child, budget, r);
+  }
+  // If the node is also an account, show its own transfers after the children
+  if let Some(account) = account.filter(|a| !a.hidden) {
+    for transfer in &account.transfers {
+      tree.insert_item(
+        transfer_entry(transfer),
+        Placement::LastChild,
+        r,
+      );
+    }
+  }
+  tree.set_collapsed(r, true);
+}
 
-  // Create the main view
-  let mut detail_tree = TreeView::<String>::new()
+// The account tables next to the tree of totals and groupings
+fn main_view(summary: &SummedBookkeeping) -> LinearLayout {
+  let mut detail_tree = TreeView::<TreeEntry>::new()
   ;
   // First insert totals in one container
   let mut row = detail_tree.insert_item(
-    format!("Totals"),
+    "Totals".to_string().into(),
     Placement::After,
     0,
   ).unwrap();
   grouping_summary_to_tree_entries(
     &mut detail_tree,
     &summary.total,
+    |key| summary.budget.as_ref().and_then(|b| b.expected_total(summary, key)),
     row,
   );
   detail_tree.set_collapsed(0, true);
@@ -153,26 +347,340 @@ pub fn run_tui(
   // Then one container for each grouping
   for (name, gs) in &summary.groupings {
     row = detail_tree.insert_item(
-      format!("{}", name),
+      name.to_string().into(),
       Placement::After,
       row,
     ).unwrap();
+    let months = months(gs);
     grouping_summary_to_tree_entries(
       &mut detail_tree,
       gs,
+      |key| summary.budget.as_ref().and_then(|b| b.expected(name, months, key)),
       row
     );
     detail_tree.set_collapsed(row, true);
   }
-  //let main = LinearView::vertical()
-  //  .child(
-  //  )
-  //  .child(
-  //  )
-  //;
-  siv.add_layer(
-    ScrollView::new(detail_tree).with_name("detail_tree")
+  LinearLayout::horizontal()
+    .child(
+      ScrollView::new(account_tables(summary)).fixed_width(50)
+    )
+    .child(
+      ScrollView::new(detail_tree.with_name("detail_tree"))
+    )
+}
+
+// Load and calculate the bookkeeping again, with the panic of an invalid
+// bookkeeping as the error instead of tearing down the terminal
+fn reload() -> Result<SummedBookkeeping, String> {
+  catch_panic(|| calculate(load(&mut StdFileIO{})))
+}
+
+fn accounts(s: &mut Cursive) -> BTreeSet<String> {
+  s.with_user_data(|summary: &mut SummedBookkeeping| summary.account_info.keys().cloned().collect())
+    .unwrap_or_default()
+}
+
+fn content(s: &mut Cursive, name: &str) -> String {
+  s.call_on_name(name, |e: &mut EditView| e.get_content().to_string()).unwrap_or_default()
+}
+
+fn row_count(s: &mut Cursive, list: &str) -> usize {
+  s.call_on_name(list, |l: &mut LinearLayout| l.len()).unwrap_or(0)
+}
+
+// The transaction as entered in the form, keeping the other fields of the
+// base. Account names may be given by any unique start, and one transfer may
+// leave its amount empty to balance.
+fn read_form(s: &mut Cursive, base: &Transaction) -> Result<Transaction, String> {
+  let accounts = accounts(s);
+  let raw_date = content(s, "form_date");
+  let date = serde_yaml::from_str(&raw_date)
+    .map_err(|_| format!("Invalid date {}, expected YYYY-MM-DD", raw_date))?
+  ;
+  let mut transfers = Vec::new();
+  let mut unbalanced = None;
+  for i in 0..row_count(s, "form_transfers") {
+    let typed = content(s, &format!("form_account_{}", i));
+    let raw_amount = content(s, &format!("form_amount_{}", i));
+    if typed.trim().is_empty() && raw_amount.trim().is_empty() { continue; }
+    let account = match complete(&accounts, typed.trim()).as_slice() {
+      [account] => account.to_string(),
+      [] => return Err(format!("No account matches {}", typed)),
+      _ => return Err(format!("Several accounts match {}", typed)),
+    };
+    if raw_amount.trim().is_empty() {
+      if unbalanced.is_some() { return Err("Only one amount can be left empty".to_owned()); }
+      unbalanced = Some(transfers.len());
+      transfers.push((account, Decimal::ZERO));
+      continue;
+    }
+    let amount = raw_amount.trim().parse()
+      .map_err(|_| format!("Invalid amount {}", raw_amount))?
+    ;
+    transfers.push((account, amount));
+  }
+  if let Some(i) = unbalanced {
+    transfers[i].1 = remaining(&transfers);
+  }
+  let mut comments = std::collections::HashMap::new();
+  for i in 0..row_count(s, "form_comments") {
+    let key = content(s, &format!("form_comment_key_{}", i));
+    let value = content(s, &format!("form_comment_value_{}", i));
+    if key.trim().is_empty() && value.trim().is_empty() { continue; }
+    if key.trim().is_empty() { return Err(format!("The comment {} needs a key", value)); }
+    comments.insert(key.trim().to_owned(), value);
+  }
+  let transaction = Transaction{
+    name: content(s, "form_name").trim().to_owned(),
+    date,
+    transfers,
+    comments,
+    ..base.clone()
+  };
+  validate_new(&transaction, &accounts)?;
+  Ok(transaction)
+}
+
+fn update_remaining(s: &mut Cursive) {
+  let amounts: Vec<(String, Decimal)> = (0..row_count(s, "form_transfers"))
+    .filter_map(|i| content(s, &format!("form_amount_{}", i)).trim().parse().ok())
+    .map(|amount| (String::new(), amount))
+    .collect()
+  ;
+  let text = format!("Remaining to balance: {}", remaining(&amounts));
+  s.call_on_name("form_remaining", |t: &mut TextView| t.set_content(text));
+}
+
+fn add_transfer_row(s: &mut Cursive, account: &str, amount: &str) {
+  let i = row_count(s, "form_transfers");
+  let row = LinearLayout::horizontal()
+    .child(
+      EditView::new()
+        .content(account)
+        .on_edit(|s, typed, _| {
+          let accounts = accounts(s);
+          let matches = complete(&accounts, typed.trim()).join(", ");
+          s.call_on_name("form_matches", |t: &mut TextView| t.set_content(matches));
+        })
+        .with_name(format!("form_account_{}", i))
+        .fixed_width(29)
+    )
+    .child(DummyView.fixed_width(1))
+    .child(
+      EditView::new()
+        .content(amount)
+        .on_edit(|s, _, _| update_remaining(s))
+        .with_name(format!("form_amount_{}", i))
+        .fixed_width(14)
+    )
+  ;
+  s.call_on_name("form_transfers", |l: &mut LinearLayout| l.add_child(row));
+}
+
+fn add_comment_row(s: &mut Cursive, key: &str, value: &str) {
+  let i = row_count(s, "form_comments");
+  let row = LinearLayout::horizontal()
+    .child(EditView::new().content(key).with_name(format!("form_comment_key_{}", i)).fixed_width(14))
+    .child(DummyView.fixed_width(1))
+    .child(EditView::new().content(value).with_name(format!("form_comment_value_{}", i)).fixed_width(29))
+  ;
+  s.call_on_name("form_comments", |l: &mut LinearLayout| l.add_child(row));
+}
+
+// Show the bookkeeping as it is after writing to the file, or why it fails to
+// load now
+fn show_written(s: &mut Cursive, written: &str) {
+  match reload() {
+    Ok(summary) => {
+      s.pop_layer();
+      s.add_layer(main_view(&summary));
+      s.set_user_data(summary);
+    },
+    Err(e) => s.add_layer(Dialog::info(format!("{}, but the bookkeeping fails to load:\n{}", written, e))),
+  }
+}
+
+// A form for the transaction, saved through the given function when valid
+fn open_transaction_dialog(
+  s: &mut Cursive,
+  title: &str,
+  transaction: Transaction,
+  save: impl Fn(&Transaction) -> Result<PathBuf, String> + Send + Sync + 'static,
+) {
+  let form = LinearLayout::vertical()
+    .child(TextView::new("Date"))
+    .child(EditView::new().content(transaction.date.to_string()).with_name("form_date").fixed_width(14))
+    .child(TextView::new("Name"))
+    .child(EditView::new().content(transaction.name.clone()).with_name("form_name").fixed_width(44))
+    .child(TextView::new("Account                        Amount"))
+    .child(LinearLayout::vertical().with_name("form_transfers"))
+    .child(Button::new("Add transfer", |s| add_transfer_row(s, "", "")))
+    .child(TextView::new("").with_name("form_matches"))
+    .child(TextView::new("").with_name("form_remaining"))
+    .child(TextView::new("Comment        Value"))
+    .child(LinearLayout::vertical().with_name("form_comments"))
+    .child(Button::new("Add comment", |s| add_comment_row(s, "", "")))
+    .child(TextView::new("").with_name("form_status"))
+  ;
+  let base = transaction.clone();
+  s.add_layer(
+    Dialog::around(ScrollView::new(form))
+      .title(title)
+      .button("Save", move |s| {
+        let saved = read_form(s, &base).and_then(|t| save(&t).map(|path| (t, path)));
+        match saved {
+          Ok((t, path)) => {
+            s.pop_layer();
+            show_written(s, &format!("Saved {} to {}", t.name, path.display()));
+          },
+          Err(e) => { s.call_on_name("form_status", |t: &mut TextView| t.set_content(e)); },
+        }
+      })
+      .dismiss_button("Cancel")
+  );
+  for (account, amount) in &transaction.transfers {
+    add_transfer_row(s, account, &amount.to_string());
+  }
+  // At least two rows to fill in
+  for _ in transaction.transfers.len()..2 {
+    add_transfer_row(s, "", "");
+  }
+  let mut comments: Vec<_> = transaction.comments.iter().collect();
+  comments.sort();
+  for (key, value) in comments {
+    add_comment_row(s, key, value);
+  }
+  update_remaining(s);
+}
+
+// The keys for the dialogs only apply to the main view
+fn in_dialog(s: &mut Cursive) -> bool {
+  s.screen().len() > 1
+}
+
+// A new transaction, added to the grouping covering its date
+fn open_add_dialog(s: &mut Cursive) {
+  if in_dialog(s) { return; }
+  let today = time::OffsetDateTime::now_utc().date();
+  open_transaction_dialog(s, "Add transaction", new_transaction("", today, Vec::new()), |t| {
+    add_transaction(&mut StdFileIO{}, t, |io| catch_panic(|| { calculate(load(io)); }))
+  });
+}
+
+// The transaction of the selected transfer in the detail tree, as written
+fn selected_transaction(s: &mut Cursive) -> Option<Result<(Source, Transaction), String>> {
+  let transfer = s.call_on_name("detail_tree", |tree: &mut TreeView<TreeEntry>| {
+    tree.row().and_then(|row| tree.borrow_item(row)).and_then(|entry| entry.transfer.clone())
+  })??;
+  Some(match (transfer.source, transfer.generated_by) {
+    (Some(source), _) => written_transaction(&mut StdFileIO{}, &source).map(|t| (source, t)),
+    (None, Some(by)) => Err(format!("{} is generated by {}, change that instead", transfer.name, by)),
+    (None, None) => Err(format!("Where {} is written is unknown", transfer.name)),
+  })
+}
+
+fn open_edit_dialog(s: &mut Cursive) {
+  if in_dialog(s) { return; }
+  match selected_transaction(s) {
+    None => {},
+    Some(Err(e)) => s.add_layer(Dialog::info(e)),
+    Some(Ok((source, old))) => {
+      open_transaction_dialog(s, "Edit transaction", old.clone(), move |t| {
+        replace_transaction(&mut StdFileIO{}, &source, &old, Some(t), |io| {
+          catch_panic(|| { calculate(load(io)); })
+        })
+      });
+    },
+  }
+}
+
+fn open_delete_dialog(s: &mut Cursive) {
+  if in_dialog(s) { return; }
+  match selected_transaction(s) {
+    None => {},
+    Some(Err(e)) => s.add_layer(Dialog::info(e)),
+    Some(Ok((source, old))) => {
+      let question = format!("Delete {} on {}?", old.name, old.date);
+      s.add_layer(
+        Dialog::text(question)
+          .title("Delete transaction")
+          .button("Delete", move |s| {
+            s.pop_layer();
+            let deleted = replace_transaction(&mut StdFileIO{}, &source, &old, None, |io| {
+              catch_panic(|| { calculate(load(io)); })
+            });
+            match deleted {
+              Ok(path) => show_written(s, &format!("Deleted {} from {}", old.name, path.display())),
+              Err(e) => s.add_layer(Dialog::info(e)),
+            }
+          })
+          .dismiss_button("Cancel")
+      );
+    },
+  }
+}
+
+// Show the transfers matching the query over the query dialog, so closing
+// them returns to the query to refine it
+fn run_query(s: &mut Cursive) {
+  let raw = content(s, "query_text");
+  let rows = catch_panic(|| load(&mut StdFileIO{}))
+    .and_then(|real| parse_query(&raw, &real).map(|q| query_rows(&real, &q)))
+  ;
+  let rows = match rows {
+    Ok(rows) => rows,
+    Err(e) => {
+      s.call_on_name("query_status", |t: &mut TextView| t.set_content(e));
+      return;
+    },
+  };
+  s.call_on_name("query_status", |t: &mut TextView| t.set_content(""));
+  let total: Decimal = rows.iter().map(|r| r.amount).sum();
+  let title = format!("{} transfers, total {}", rows.len(), total);
+  s.add_layer(
+    Dialog::around(
+      TableView::<QueryRow, QueryColumn>::new()
+        .column(QueryColumn::Date, "Date", |c| c.width(12))
+        .column(QueryColumn::Grouping, "Grouping", |c| c.width(14))
+        .column(QueryColumn::Transaction, "Transaction", |c| c.width_percent(35))
+        .column(QueryColumn::Account, "Account", |c| c)
+        .column(QueryColumn::Amount, "Amount", |c| c.align(HAlign::Right))
+        .items(rows)
+        .min_size((110, 30))
+    )
+      .title(title)
+      .dismiss_button("Close")
   );
+}
+
+// A query as for the query command
+fn open_query_dialog(s: &mut Cursive) {
+  if in_dialog(s) { return; }
+  s.add_layer(
+    Dialog::around(
+      LinearLayout::vertical()
+        .child(TextView::new("account:NAME type:TYPE sum:NAME date:FROM..TO amount>N\nname:REGEX comment:KEY[=REGEX], combined with not, - and or"))
+        .child(EditView::new().on_submit(|s, _| run_query(s)).with_name("query_text").fixed_width(70))
+        .child(TextView::new("").with_name("query_status"))
+    )
+      .title("Query transfers")
+      .button("Search", run_query)
+      .dismiss_button("Close")
+  );
+}
+
+pub fn run_tui(
+  summary: SummedBookkeeping,
+) {
+  let mut siv = Cursive::new();
+  siv.add_global_callback('q', |s| s.quit());
+  siv.add_global_callback('a', open_add_dialog);
+  siv.add_global_callback('e', open_edit_dialog);
+  siv.add_global_callback('d', open_delete_dialog);
+  siv.add_global_callback('/', open_query_dialog);
+
+  siv.add_layer(main_view(&summary));
+  siv.set_user_data(summary);
 
   siv.run();
 }
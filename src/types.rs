@@ -5,6 +5,7 @@ use serde::{
   Serialize,
   Deserialize,
 };
+use schemars::JsonSchema;
 use rust_decimal::Decimal;
 
 use std::path::PathBuf;
@@ -12,8 +13,47 @@ use serde_yaml::from_str;
 use time::Date;
 
 use super::FileIO;
+use super::budget::Budget;
+use super::accrual::{
+  MonthRange,
+  accrue,
+};
+use super::open_items::Reference;
+use super::sums::resolve_account_sums;
+use super::schema::{
+  Day,
+  yaml_tags,
+};
+use super::metrics::{
+  Metric,
+  validate_metrics,
+};
+use super::invoice::{
+  Invoice,
+  Invoicing,
+  validate_numbers,
+};
+use super::asset::{
+  Asset,
+  RealAsset,
+  depreciations,
+};
+use super::loan::{
+  Loan,
+  loan_payments,
+};
+use super::recurring::{
+  Recurring,
+  expand_recurring,
+};
+use super::vat::{
+  VatCode,
+  VatAccounts,
+  VatLine,
+  split_vat,
+};
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "snake_case")]
 pub enum AccountType {
   // Source of money
@@ -32,7 +72,56 @@ pub enum AccountType {
   // period.)
   YearlyResult,
 }
-#[derive(Debug, PartialEq, Serialize)]
+/// Optional details about an account, given instead of just its name.
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct AccountInfo {
+  pub name: String,
+  // Such as the BAS account number
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub number: Option<u32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  // Transfers are only allowed from the opened date until the closed date
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  #[schemars(with = "Option<Day>")]
+  pub opened: Option<Date>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  #[schemars(with = "Option<Day>")]
+  pub closed: Option<Date>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub currency: Option<String>,
+  // Still summed, but not shown in the TUI
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub hidden: bool,
+}
+impl AccountInfo {
+  pub fn is_open(&self, date: Date) -> bool {
+    self.opened.is_none_or(|d| d <= date) && self.closed.is_none_or(|d| date <= d)
+  }
+}
+/// An account is declared either by only its name or with its details.
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(untagged)]
+pub enum AccountDeclaration {
+  Name(String),
+  Info(AccountInfo),
+}
+impl AccountDeclaration {
+  pub fn name(&self) -> &str {
+    match self {
+      AccountDeclaration::Name(name) => name,
+      AccountDeclaration::Info(info) => &info.name,
+    }
+  }
+  pub fn info(self) -> AccountInfo {
+    match self {
+      AccountDeclaration::Name(name) => AccountInfo{ name, ..Default::default() },
+      AccountDeclaration::Info(info) => info,
+    }
+  }
+}
+
+#[derive(Debug, PartialEq, Serialize, Clone)]
 pub struct RealBookkeeping {
   // A recognizeable name. Basically just a comment
   pub name: String,
@@ -42,37 +131,173 @@ pub struct RealBookkeeping {
   // All accounts and their type with order preserved
   #[serde(with = "tuple_vec_map")]
   pub account_types: Vec<(AccountType, Vec<String>)>,
-  // Secondary sums of these are created from the account sums
+  // The details of every account, defaulted if only the name is given
+  pub account_info: std::collections::BTreeMap<String, AccountInfo>,
+  // Secondary sums of these are created from the account sums, resolved into
+  // the accounts and the factor (1 or -1, more if included several times)
   #[serde(with = "tuple_vec_map")]
-  pub account_sums: Vec<(String, Vec<String>)>,
+  pub account_sums: Vec<(String, Vec<(String, Decimal)>)>,
+  // Where VAT is booked when splitting gross amounts, if VAT is used at all
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub vat: Option<VatAccounts>,
+  // Expected sums to compare the calculated sums with
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub budget: Option<Budget>,
+  // Kept for projecting the loans beyond the bookkeeping
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub loans: Vec<Loan>,
+  // The asset register, with the purchases looked up
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub assets: Vec<RealAsset>,
+  // Kept for rendering the invoices
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub invoicing: Option<Invoicing>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub invoices: Vec<Invoice>,
+  // Evaluated by calculate for each grouping and the total
+  #[serde(with = "tuple_vec_map", skip_serializing_if = "Vec::is_empty")]
+  pub metrics: Vec<(String, Metric)>,
   // Contains all the transaction data
   pub groupings: Vec<RealGrouping>,
 }
+impl RealBookkeeping {
+  pub fn account_type(&self, account: &str) -> Option<AccountType> {
+    self.account_types.iter()
+      .find(|(_, accounts)| accounts.iter().any(|a| a == account))
+      .map(|(t, _)| *t)
+  }
+  pub fn account_info(&self, account: &str) -> Option<&AccountInfo> {
+    self.account_info.get(account)
+  }
+}
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Bookkeeping {
+  // The version of the file format, see migrate
+  pub version: u64,
   pub name: String,
   #[serde(with = "tuple_vec_map")]
-  pub accounts: Vec<(AccountType, Vec<String>)>,
+  #[schemars(with = "std::collections::BTreeMap<AccountType, Vec<AccountDeclaration>>")]
+  pub accounts: Vec<(AccountType, Vec<AccountDeclaration>)>,
+  // Each entry is an account, another sum or a pattern like "home:*", any of
+  // which can be prefixed with "-" to subtract it
   #[serde(with = "tuple_vec_map")]
+  #[schemars(with = "std::collections::BTreeMap<String, Vec<String>>")]
   pub account_sums: Vec<(String, Vec<String>)>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub vat: Option<VatAccounts>,
+  // Path to a budget file
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub budget: Option<PathBuf>,
+  // Transactions generated into every grouping with a period
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub recurring: Vec<Recurring>,
+  // Loans with payments generated into every grouping with a period
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub loans: Vec<Loan>,
+  // Assets with depreciation generated into every grouping with a period
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub assets: Vec<Asset>,
+  // Asset account accrued expenses are booked on until their months
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub prepaid_account: Option<String>,
+  // Needed for invoices in the groupings
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub invoicing: Option<Invoicing>,
+  // Named formulas over account sums, accounts and account types
+  #[serde(default, with = "tuple_vec_map", skip_serializing_if = "Vec::is_empty")]
+  #[schemars(with = "std::collections::BTreeMap<String, Metric>")]
+  pub metrics: Vec<(String, Metric)>,
   pub groupings: Vec<Grouping>,
 }
 impl Bookkeeping {
   pub fn realize(mut self, io: &mut impl FileIO) -> RealBookkeeping {
-    let real = RealBookkeeping{
+    let mut real = RealBookkeeping{
       name: self.name,
       accounts: self.accounts.iter()
         .fold(std::collections::BTreeSet::new(), |mut m, (_, accounts)| {
           for account in accounts {
-            m.insert(account.to_owned());
+            m.insert(account.name().to_owned());
           }
           m
         }),
-      account_types: self.accounts,
-      account_sums: self.account_sums,
-      groupings: self.groupings.drain(..).map(|m| m.realize(io)).collect(),
+      account_types: self.accounts.iter()
+        .map(|(t, accounts)| (*t, accounts.iter().map(|a| a.name().to_owned()).collect()))
+        .collect(),
+      account_info: self.accounts.into_iter()
+        .flat_map(|(_, accounts)| accounts)
+        .map(|a| { let info = a.info(); (info.name.clone(), info) })
+        .collect(),
+      account_sums: Vec::new(),
+      vat: self.vat,
+      budget: None,
+      loans: Vec::new(),
+      assets: Vec::new(),
+      invoicing: None,
+      invoices: Vec::new(),
+      metrics: Vec::new(),
+      groupings: Vec::new(),
     };
+    real.account_sums = resolve_account_sums(&self.account_sums, &real.accounts);
+    // Read in all transactions first, so generated ones can be added
+    let mut groupings: Vec<Grouping> = self.groupings.drain(..).map(|m| m.read(io)).collect();
+    accrue(&mut groupings, self.prepaid_account.as_deref(), &real);
+    let assets: Vec<RealAsset> = self.assets.iter().map(|a| a.realize(&groupings, &real)).collect();
+    let mut invoices = Vec::new();
+    for grouping in groupings.iter_mut() {
+      if !grouping.invoices.is_empty() {
+        let invoicing = self.invoicing.as_ref()
+          .unwrap_or_else(|| panic!("Grouping {} has invoices but invoicing isn't configured.", grouping.name))
+        ;
+        let generated: Vec<Transaction> = grouping.invoices.iter().map(|i| i.transaction(invoicing)).collect();
+        invoices.extend(grouping.invoices.iter().cloned());
+        grouping.transactions_mut().extend(generated);
+      }
+      if let Some(period) = grouping.period {
+        let transactions = grouping.transactions_mut();
+        transactions.extend(expand_recurring(&self.recurring, &period));
+        transactions.extend(loan_payments(&self.loans, &period));
+        transactions.extend(depreciations(&assets, &period));
+      }
+    }
+    // Realizing transactions needs the accounts, so add the groupings after
+    let groupings = groupings.drain(..).map(|m| m.realize(io, &real)).collect();
+    real.groupings = groupings;
+    real.loans = self.loans;
+    real.assets = assets;
+    if let Some(invoicing) = &self.invoicing {
+      validate_numbers(&invoices, invoicing);
+    }
+    real.invoicing = self.invoicing;
+    real.invoices = invoices;
+    validate_metrics(&self.metrics, &real);
+    real.metrics = self.metrics;
+    if let Some(path) = self.budget {
+      let raw = io.read_path(&path);
+      let budget: Budget = from_str(&raw)
+        .unwrap_or_else(|_| panic!("Invalid format at {}", path.display()))
+      ;
+      budget.validate(&real);
+      real.budget = Some(budget);
+    }
+    for account in &real.accounts {
+      if account.split(':').any(|part| part.is_empty()) {
+        panic!("Account {} has an empty part in its hierarchy, invalid.", account);
+      }
+    }
+    for transaction in real.groupings.iter().flat_map(|g| &g.transactions) {
+      for (account, _) in &transaction.transfers {
+        let info = match real.account_info(account) {
+          Some(info) => info,
+          None => continue,
+        };
+        if !info.is_open(transaction.date) {
+          panic!("Transaction {} on {} transfers to account {} which isn't open then.",
+            transaction.name, transaction.date, account,
+          );
+        }
+      }
+    }
     real.groupings.iter().fold(std::collections::HashSet::new(), |mut s, m|{
       if !s.insert(&m.name) { panic!("Duplicate grouping {}", m.name); }
       s
@@ -82,26 +307,91 @@ impl Bookkeeping {
 }
 
 
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Debug, PartialEq, Serialize, Clone)]
 pub struct RealGrouping {
   pub name: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub period: Option<Period>,
   pub transactions: Vec<RealTransaction>,
 }
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// An inclusive range of dates
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone, Copy)]
+pub struct Period {
+  #[schemars(with = "Day")]
+  pub start: Date,
+  #[schemars(with = "Day")]
+  pub end: Date,
+}
+impl Period {
+  pub fn contains(&self, date: Date) -> bool {
+    self.start <= date && date <= self.end
+  }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Grouping {
   pub name: String,
+  // The dates the grouping covers, needed to generate transactions into it
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub period: Option<Period>,
+  // Each generates a transaction into the grouping
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub invoices: Vec<Invoice>,
   pub transactions: Transactions
 }
 impl Grouping {
-  pub fn realize(self, io: &mut impl FileIO) -> RealGrouping {
+  /// Read in any transaction files, so more transactions can be added. The
+  /// transactions read are marked with where they are written.
+  pub fn read(self, io: &mut impl FileIO) -> Grouping {
+    let transactions = match self.transactions {
+      Transactions::Inlined(mut transactions) => {
+        for (index, transaction) in transactions.iter_mut().enumerate() {
+          transaction.source = Some(Source::Inlined{ grouping: self.name.clone(), index });
+        }
+        transactions
+      },
+      Transactions::Paths(paths) => {
+        let mut transactions = Vec::new();
+        for path in paths {
+          let mut read = Transactions::Paths(vec![path.clone()]).read(io);
+          for (index, transaction) in read.iter_mut().enumerate() {
+            transaction.source = Some(Source::Path{ path: path.clone(), index });
+          }
+          transactions.append(&mut read);
+        }
+        transactions
+      },
+    };
+    Grouping{
+      transactions: Transactions::Inlined(transactions),
+      ..self
+    }
+  }
+  /// The transactions of a grouping that has been read, for looking up.
+  pub fn transactions(&self) -> &[Transaction] {
+    match &self.transactions {
+      Transactions::Inlined(i) => i,
+      Transactions::Paths(_) => panic!("Grouping {} must be read before looking in it", self.name),
+    }
+  }
+  /// The transactions of a grouping that has been read.
+  pub fn transactions_mut(&mut self) -> &mut Vec<Transaction> {
+    match &mut self.transactions {
+      Transactions::Inlined(i) => i,
+      Transactions::Paths(_) => panic!("Grouping {} must be read before adding to it", self.name),
+    }
+  }
+  pub fn realize(self, io: &mut impl FileIO, book: &RealBookkeeping) -> RealGrouping {
     RealGrouping{
       name: self.name,
-      transactions: self.transactions.realize(io)
+      period: self.period,
+      transactions: self.transactions.realize(io, book)
     }
   }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+#[schemars(transform = yaml_tags)]
 pub enum Transactions {
   /// The yaml is inlined
   Inlined(Vec<Transaction>),
@@ -116,23 +406,38 @@ impl Transactions {
         let mut transactions = Vec::new();
         for path in paths {
           let raw = io.read_path(&path);
-          transactions.append(&mut from_str(&raw).expect(&format!("Invalid format at {}", path.display())))
+          transactions.append(&mut from_str(&raw).unwrap_or_else(|_| panic!("Invalid format at {}", path.display())))
         }
         transactions
       }
     }
   }
-  pub fn realize(self, io: &mut impl FileIO) -> Vec<RealTransaction> {
-    self.read(io).drain(..).enumerate().map(|(i,x)| RealTransaction{
-      name: x.name,
-      date: x.date,
-      index: i,
-      transfers: x.transfers,
-      comments: x.comments,
-    }).collect()
+  pub fn realize(self, io: &mut impl FileIO, book: &RealBookkeeping) -> Vec<RealTransaction> {
+    self.read(io).drain(..).enumerate().map(|(i,x)| x.realize(i, book)).collect()
   }
 }
 
+/// Where a transaction is written, so it can be changed in place.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum Source {
+  /// In the inlined transactions of a grouping in bookkeeping.yaml
+  Inlined{ grouping: String, index: usize },
+  /// In a transaction file
+  Path{ path: PathBuf, index: usize },
+}
+
+/// Pair values given by account, such as VAT codes, with the transfers of a
+/// transaction. If an account has several transfers they get its values in
+/// order.
+pub fn by_transfer<T: Clone>(transfers: &[(String, Decimal)], values: &[(String, T)]) -> Vec<Option<T>> {
+  transfers.iter().enumerate()
+    .map(|(i, (account, _))| {
+      let nth = transfers[..i].iter().filter(|(a, _)| a == account).count();
+      values.iter().filter(|(a, _)| a == account).nth(nth).map(|(_, value)| value.clone())
+    })
+    .collect()
+}
+
 #[derive(Debug, PartialEq, Serialize, Clone)]
 pub struct RealTransaction {
   pub name: String,
@@ -140,17 +445,84 @@ pub struct RealTransaction {
   pub index: usize,
   #[serde(with = "tuple_vec_map")]
   pub transfers: Vec<(String, Decimal)>,
+  // How the VAT coded transfers were split, kept for the VAT return
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub vat: Vec<VatLine>,
+  // The reference of each transfer, empty if the transaction has none
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub references: Vec<Option<Reference>>,
   pub comments: std::collections::HashMap<String, String>,
+  // What created the transaction, if it wasn't written by hand
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub generated_by: Option<String>,
+  #[serde(skip)]
+  pub source: Option<Source>,
 }
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Transaction {
   pub name: String,
+  #[schemars(with = "Day")]
   pub date: Date,
   #[serde(with = "tuple_vec_map")]
+  #[schemars(with = "std::collections::BTreeMap<String, Decimal>")]
   pub transfers: Vec<(String, Decimal)>,
+  // VAT codes for transfers given as gross amounts, by account
+  #[serde(default, with = "tuple_vec_map", skip_serializing_if = "Vec::is_empty")]
+  #[schemars(with = "std::collections::BTreeMap<String, VatCode>")]
+  pub vat: Vec<(String, VatCode)>,
+  // Months to spread the expenses over, through the prepaid account
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub accrue: Option<MonthRange>,
+  // Opens or settles an item on the debtor and creditor accounts
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub reference: Option<Reference>,
+  // References for single transfers by account, over the one above
+  #[serde(default, with = "tuple_vec_map", skip_serializing_if = "Vec::is_empty")]
+  #[schemars(with = "std::collections::BTreeMap<String, Reference>")]
+  pub references: Vec<(String, Reference)>,
   // To keep paths to receipts/bills/descriptions...
   #[serde(flatten)]
   pub comments: std::collections::HashMap<String, String>,
+  #[serde(skip)]
+  pub generated_by: Option<String>,
+  #[serde(skip)]
+  pub source: Option<Source>,
+}
+impl RealTransaction {
+  /// The reference of the transfer at the index, if any.
+  pub fn reference(&self, index: usize) -> Option<&Reference> {
+    self.references.get(index).and_then(|r| r.as_ref())
+  }
+}
+impl Transaction {
+  pub fn realize(self, index: usize, book: &RealBookkeeping) -> RealTransaction {
+    for (account, _) in &self.references {
+      let referenced = self.references.iter().filter(|(a, _)| a == account).count();
+      if referenced > self.transfers.iter().filter(|(a, _)| a == account).count() {
+        panic!("Transaction {} has more references for {} than transfers to it.", self.name, account);
+      }
+    }
+    // The VAT transfers split off below are added last, so they get none
+    let references = if self.reference.is_none() && self.references.is_empty() {
+      Vec::new()
+    } else {
+      by_transfer(&self.transfers, &self.references).into_iter()
+        .map(|r| r.or_else(|| self.reference.clone()))
+        .collect()
+    };
+    let (transfers, vat) = split_vat(&self.name, self.transfers, &self.vat, book);
+    RealTransaction{
+      name: self.name,
+      date: self.date,
+      index,
+      transfers,
+      vat,
+      references,
+      comments: self.comments,
+      generated_by: self.generated_by,
+      source: self.source,
+    }
+  }
 }
 // 
 // #[cfg(test)]
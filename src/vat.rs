@@ -0,0 +1,343 @@
+//! Swedish VAT (moms) handling.
+//!
+//! Transfers can be given a VAT code, in which case their amount is the gross
+//! amount. When realizing the transaction it is split into the net amount on
+//! the given account and the VAT on the configured VAT account. Since the
+//! parts add up to the gross amount the transaction still sums to 0.
+//!
+//! Transfers on income accounts are treated as sales (output VAT), transfers
+//! on all other accounts as purchases (input VAT).
+
+use serde::{
+  Serialize,
+  Deserialize,
+};
+use schemars::JsonSchema;
+use rust_decimal::Decimal;
+
+use crate::types::*;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VatCode {
+  Vat25,
+  Vat12,
+  Vat6,
+  // Sold or bought without VAT, only reported
+  Exempt,
+  // The buyer accounts for the VAT (25%) instead of the seller. For purchases
+  // both input and output VAT is booked, for sales no VAT is booked.
+  ReverseCharge,
+}
+impl VatCode {
+  pub fn rate(&self) -> Decimal {
+    match self {
+      VatCode::Vat25 => Decimal::new(25, 2),
+      VatCode::Vat12 => Decimal::new(12, 2),
+      VatCode::Vat6 => Decimal::new(6, 2),
+      VatCode::Exempt => Decimal::ZERO,
+      VatCode::ReverseCharge => Decimal::new(25, 2),
+    }
+  }
+}
+
+/// The accounts VAT is booked on. Should be declared as creditor accounts.
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct VatAccounts {
+  pub output_25: String,
+  pub output_12: String,
+  pub output_6: String,
+  pub input: String,
+  // Output VAT calculated on reverse charge purchases
+  pub reverse_charge: String,
+}
+impl VatAccounts {
+  pub fn contains(&self, account: &str) -> bool {
+    [&self.output_25, &self.output_12, &self.output_6, &self.input, &self.reverse_charge]
+      .iter().any(|a| *a == account)
+  }
+  fn output(&self, code: VatCode) -> &str {
+    match code {
+      VatCode::Vat25 => &self.output_25,
+      VatCode::Vat12 => &self.output_12,
+      VatCode::Vat6 => &self.output_6,
+      VatCode::Exempt => unreachable!("Exempt transfers have no output VAT"),
+      VatCode::ReverseCharge => &self.reverse_charge,
+    }
+  }
+}
+
+/// How one VAT coded transfer was split. Amounts have the sign they were booked
+/// with, so sales are negative.
+#[derive(Debug, PartialEq, Serialize, Clone)]
+pub struct VatLine {
+  pub account: String,
+  pub code: VatCode,
+  pub sale: bool,
+  pub net: Decimal,
+  pub vat: Decimal,
+}
+
+fn add_transfer(transfers: &mut Vec<(String, Decimal)>, account: &str, amount: Decimal) {
+  match transfers.iter_mut().find(|(a, _)| a == account) {
+    Some((_, sum)) => *sum += amount,
+    None => transfers.push((account.to_owned(), amount)),
+  }
+}
+
+/// Split the VAT coded transfers of a transaction into net transfers and
+/// transfers to the VAT accounts.
+pub fn split_vat(
+  transaction: &str,
+  transfers: Vec<(String, Decimal)>,
+  codes: &[(String, VatCode)],
+  book: &RealBookkeeping,
+) -> (Vec<(String, Decimal)>, Vec<VatLine>) {
+  if codes.is_empty() { return (transfers, Vec::new()); }
+  let accounts = book.vat.as_ref()
+    .unwrap_or_else(|| panic!("Transaction {} has VAT codes but no vat accounts are configured.", transaction))
+  ;
+  for (account, _) in codes {
+    let coded = codes.iter().filter(|(a, _)| a == account).count();
+    if coded > transfers.iter().filter(|(a, _)| a == account).count() {
+      panic!("Transaction {} has more VAT codes for {} than transfers to it.", transaction, account);
+    }
+  }
+
+  let mut net_transfers = Vec::new();
+  let mut vat_transfers = Vec::new();
+  let mut lines = Vec::new();
+  let transfer_codes = by_transfer(&transfers, codes);
+  for ((account, amount), code) in transfers.into_iter().zip(transfer_codes) {
+    let code = match code {
+      Some(code) => code,
+      None => { net_transfers.push((account, amount)); continue; },
+    };
+    let sale = book.account_type(&account) == Some(AccountType::Income);
+    let (net, vat) = match code {
+      VatCode::Exempt => (amount, Decimal::ZERO),
+      // Reverse charge sales have no VAT, the buyer handles it
+      VatCode::ReverseCharge if sale => (amount, Decimal::ZERO),
+      // Reverse charge purchases are booked net, VAT is both due and deductible
+      VatCode::ReverseCharge => {
+        let vat = (amount * code.rate()).round_dp(2);
+        add_transfer(&mut vat_transfers, &accounts.input, vat);
+        add_transfer(&mut vat_transfers, &accounts.reverse_charge, -vat);
+        (amount, vat)
+      },
+      _ => {
+        let net = (amount / (Decimal::ONE + code.rate())).round_dp(2);
+        let vat = amount - net;
+        let vat_account = if sale { accounts.output(code) } else { &accounts.input };
+        add_transfer(&mut vat_transfers, vat_account, vat);
+        (net, vat)
+      },
+    };
+    net_transfers.push((account.clone(), net));
+    lines.push(VatLine{ account, code, sale, net, vat });
+  }
+  for (account, amount) in vat_transfers {
+    add_transfer(&mut net_transfers, &account, amount);
+  }
+  (net_transfers, lines)
+}
+
+/// The boxes of the Swedish VAT return (momsdeklaration) that can be derived
+/// from the VAT codes. All amounts are positive as on the form.
+#[derive(Debug, Serialize, Default, Clone)]
+pub struct VatReturn {
+  // Momspliktig försäljning
+  pub box_05: Decimal,
+  // Utgående moms 25%, 12% and 6%
+  pub box_10: Decimal,
+  pub box_11: Decimal,
+  pub box_12: Decimal,
+  // Inköp av tjänster från annat EU-land (reverse charge purchases)
+  pub box_21: Decimal,
+  // Utgående moms 25% on the reverse charge purchases
+  pub box_30: Decimal,
+  // Försäljning av tjänster till näringsidkare i annat EU-land (reverse charge sales)
+  pub box_39: Decimal,
+  // Övrig försäljning, momsfri (exempt sales)
+  pub box_42: Decimal,
+  // Ingående moms att dra av
+  pub box_48: Decimal,
+  // Moms att betala (positive) eller få tillbaka (negative)
+  pub box_49: Decimal,
+}
+impl VatReturn {
+  fn add(&mut self, line: &VatLine) {
+    match (line.sale, line.code) {
+      (true, VatCode::Exempt) => self.box_42 -= line.net,
+      (true, VatCode::ReverseCharge) => self.box_39 -= line.net,
+      (true, code) => {
+        self.box_05 -= line.net;
+        match code {
+          VatCode::Vat25 => self.box_10 -= line.vat,
+          VatCode::Vat12 => self.box_11 -= line.vat,
+          VatCode::Vat6 => self.box_12 -= line.vat,
+          _ => unreachable!(),
+        }
+      },
+      (false, VatCode::Exempt) => {},
+      (false, VatCode::ReverseCharge) => {
+        self.box_21 += line.net;
+        self.box_30 += line.vat;
+        self.box_48 += line.vat;
+      },
+      (false, _) => self.box_48 += line.vat,
+    }
+    self.box_49 = self.box_10 + self.box_11 + self.box_12 + self.box_30 - self.box_48;
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct VatReport {
+  pub name: String,
+  pub total: VatReturn,
+  #[serde(with = "tuple_vec_map")]
+  pub groupings: Vec<(String, VatReturn)>,
+}
+
+/// Create the VAT return for each grouping (period) and the whole bookkeeping.
+pub fn vat_report(data: &RealBookkeeping) -> VatReport {
+  let mut total = VatReturn::default();
+  let mut groupings = Vec::new();
+  for grouping in &data.groupings {
+    let mut period = VatReturn::default();
+    for line in grouping.transactions.iter().flat_map(|t| &t.vat) {
+      period.add(line);
+      total.add(line);
+    }
+    groupings.push((grouping.name.clone(), period));
+  }
+  VatReport{
+    name: data.name.clone(),
+    total,
+    groupings,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::file_io::DummyFileIO;
+
+  const BOOK: &str = "
+version: 1
+name: test
+accounts:
+  asset: [money]
+  creditor: [vat_out_25, vat_out_12, vat_out_6, vat_in, vat_reverse]
+  income: [sales, sales_eu]
+  expense: [software, services, insurance, food]
+vat:
+  output_25: vat_out_25
+  output_12: vat_out_12
+  output_6: vat_out_6
+  input: vat_in
+  reverse_charge: vat_reverse
+account_sums: {}
+groupings:
+- name: Q1
+  transactions: !Inlined
+  - name: sales
+    date: 2023-01-10
+    transfers:
+      sales: -1250
+      sales: -112
+      sales: -106
+      sales: -300
+      sales_eu: -400
+      money: 2168
+    vat:
+      sales: vat25
+      sales: vat12
+      sales: vat6
+      sales: exempt
+      sales_eu: reverse_charge
+  - name: purchases
+    date: 2023-01-20
+    transfers:
+      software: 125
+      services: 1000
+      insurance: 50
+      food: 10
+      money: -1185
+    vat:
+      software: vat25
+      services: reverse_charge
+      insurance: exempt
+      food: vat12
+";
+
+  fn d(s: &str) -> Decimal {
+    s.parse().unwrap()
+  }
+
+  fn transfers(t: &[(&str, &str)]) -> Vec<(String, Decimal)> {
+    t.iter().map(|(a, x)| (a.to_string(), d(x))).collect()
+  }
+
+  fn real() -> RealBookkeeping {
+    let book: Bookkeeping = serde_yaml::from_str(BOOK).unwrap();
+    book.realize(&mut DummyFileIO{})
+  }
+
+  #[test]
+  fn split() {
+    let real = real();
+    let sales = &real.groupings[0].transactions[0];
+    // The codes of an account go to its transfers in order
+    assert_eq!(sales.transfers, transfers(&[
+      ("sales", "-1000.00"),
+      ("sales", "-100.00"),
+      ("sales", "-100.00"),
+      ("sales", "-300"),
+      ("sales_eu", "-400"),
+      ("money", "2168"),
+      ("vat_out_25", "-250.00"),
+      ("vat_out_12", "-12.00"),
+      ("vat_out_6", "-6.00"),
+    ]));
+    let purchases = &real.groupings[0].transactions[1];
+    // Reverse charge is booked net with VAT both ways, the rest rounded net
+    assert_eq!(purchases.transfers, transfers(&[
+      ("software", "100.00"),
+      ("services", "1000"),
+      ("insurance", "50"),
+      ("food", "8.93"),
+      ("money", "-1185"),
+      ("vat_in", "276.07"),
+      ("vat_reverse", "-250.00"),
+    ]));
+    for t in [sales, purchases] {
+      assert!(t.transfers.iter().map(|(_, x)| x).sum::<Decimal>().is_zero());
+    }
+  }
+
+  #[test]
+  fn boxes() {
+    let report = vat_report(&real());
+    let r = &report.total;
+    assert_eq!(r.box_05, d("1200"));
+    assert_eq!(r.box_10, d("250"));
+    assert_eq!(r.box_11, d("12"));
+    assert_eq!(r.box_12, d("6"));
+    assert_eq!(r.box_21, d("1000"));
+    assert_eq!(r.box_30, d("250"));
+    assert_eq!(r.box_39, d("400"));
+    assert_eq!(r.box_42, d("300"));
+    assert_eq!(r.box_48, d("276.07"));
+    assert_eq!(r.box_49, d("241.93"));
+    assert_eq!(report.groupings[0].1.box_49, r.box_49);
+  }
+
+  #[test]
+  #[should_panic(expected = "more VAT codes for food")]
+  fn more_codes_than_transfers() {
+    let book = BOOK.replace("      food: vat12\n", "      food: vat12\n      food: vat6\n");
+    let book: Bookkeeping = serde_yaml::from_str(&book).unwrap();
+    book.realize(&mut DummyFileIO{});
+  }
+}
@@ -0,0 +1,184 @@
+//! Exporting the bookkeeping into an XLSX workbook, for spreadsheets such as
+//! LibreOffice Calc and Excel.
+//!
+//! The first sheet has the account type and account sum totals per grouping,
+//! followed by a sheet per grouping with its transfers and account sums.
+//! Amounts and dates are written as numbers with a format, so they can be
+//! calculated with, in the currency of their account if it has one.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use rust_xlsxwriter::{
+  ExcelDateTime,
+  Format,
+  Workbook,
+  Worksheet,
+  XlsxError,
+};
+use time::Date;
+
+use crate::types::*;
+use crate::calculate::*;
+
+fn xlsx(e: XlsxError) -> String {
+  format!("XLSX error: {}", e)
+}
+
+// The format of amounts in the currency, if any
+fn money(currency: Option<&str>) -> Format {
+  match currency {
+    Some(currency) => Format::new().set_num_format(format!("#,##0.00 \"{}\"", currency.replace('"', ""))),
+    None => Format::new().set_num_format("#,##0.00"),
+  }
+}
+
+fn number(amount: Decimal) -> f64 {
+  amount.to_f64().unwrap_or_default()
+}
+
+fn date(date: Date) -> Result<ExcelDateTime, XlsxError> {
+  ExcelDateTime::from_ymd(date.year() as u16, date.month() as u8, date.day())
+}
+
+// The name made valid for a sheet: at most 31 characters, without []:*?/\
+// and different from the names already taken regardless of case
+fn sheet_name(name: &str, taken: &mut BTreeSet<String>) -> String {
+  let cleaned: String = name.chars()
+    .map(|c| if "[]:*?/\\".contains(c) { '_' } else { c })
+    .collect()
+  ;
+  let cleaned = cleaned.trim_matches('\'');
+  let cleaned = if cleaned.is_empty() { "Grouping" } else { cleaned };
+  let mut candidate: String = cleaned.chars().take(31).collect();
+  let mut n = 1;
+  while taken.contains(&candidate.to_lowercase()) {
+    n += 1;
+    let suffix = format!(" ({})", n);
+    candidate = cleaned.chars().take(31 - suffix.len()).collect::<String>() + &suffix;
+  }
+  taken.insert(candidate.to_lowercase());
+  candidate
+}
+
+fn headers(sheet: &mut Worksheet, row: u32, col: u16, headers: &[&str], bold: &Format) -> Result<(), XlsxError> {
+  for (i, header) in headers.iter().enumerate() {
+    sheet.write_string_with_format(row, col + i as u16, *header, bold)?;
+  }
+  Ok(())
+}
+
+// The account types and account sums as rows, with a column per grouping and
+// the total
+fn overview(sheet: &mut Worksheet, summary: &SummedBookkeeping) -> Result<(), XlsxError> {
+  let bold = Format::new().set_bold();
+  let amount = money(None);
+  let bold_amount = money(None).set_bold();
+  let columns: Vec<(&str, &SummedGrouping)> = summary.groupings.iter()
+    .map(|(name, gs)| (name.as_str(), gs))
+    .chain([("Total", &summary.total)])
+    .collect()
+  ;
+  sheet.write_string_with_format(0, 0, &summary.name, &bold)?;
+  let mut row = 2;
+  let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+  headers(sheet, row, 0, &[&["Account type"], names.as_slice()].concat(), &bold)?;
+  sheet.set_freeze_panes(row + 1, 1)?;
+  for (t, _, _) in &summary.total.account_types {
+    row += 1;
+    sheet.write_string(row, 0, format!("{:?}", t))?;
+    for (i, (_, gs)) in columns.iter().enumerate() {
+      let sum = gs.account_types.iter().find(|(x, _, _)| x == t).map(|(_, sum, _)| *sum).unwrap_or_default();
+      let format = if i + 1 == columns.len() { &bold_amount } else { &amount };
+      sheet.write_number_with_format(row, 1 + i as u16, number(sum), format)?;
+    }
+  }
+  if !summary.total.account_sums.is_empty() {
+    row += 2;
+    headers(sheet, row, 0, &[&["Account sum"], names.as_slice()].concat(), &bold)?;
+    for (name, _, _) in &summary.total.account_sums {
+      row += 1;
+      sheet.write_string(row, 0, name)?;
+      for (i, (_, gs)) in columns.iter().enumerate() {
+        let sum = gs.account_sums.iter().find(|(x, _, _)| x == name).map(|(_, sum, _)| *sum).unwrap_or_default();
+        let format = if i + 1 == columns.len() { &bold_amount } else { &amount };
+        sheet.write_number_with_format(row, 1 + i as u16, number(sum), format)?;
+      }
+    }
+  }
+  sheet.autofit();
+  Ok(())
+}
+
+// The transfers of the grouping as a filterable table, with the sums of its
+// accounts beside it
+fn grouping(
+  sheet: &mut Worksheet,
+  real: &RealBookkeeping,
+  transactions: &[RealTransaction],
+  gs: &SummedGrouping,
+) -> Result<(), XlsxError> {
+  let bold = Format::new().set_bold();
+  let day = Format::new().set_num_format("yyyy-mm-dd");
+  let currency = |account: &str| real.account_info(account).and_then(|i| i.currency.clone());
+  headers(sheet, 0, 0, &["Date", "Transaction", "Number", "Account", "Description", "Amount"], &bold)?;
+  sheet.set_freeze_panes(1, 0)?;
+  let mut row = 0;
+  for t in transactions {
+    for (account, amount) in &t.transfers {
+      row += 1;
+      let info = real.account_info(account);
+      sheet.write_datetime_with_format(row, 0, date(t.date)?, &day)?;
+      sheet.write_string(row, 1, &t.name)?;
+      if let Some(n) = info.and_then(|i| i.number) {
+        sheet.write_number(row, 2, n)?;
+      }
+      sheet.write_string(row, 3, account)?;
+      if let Some(description) = info.and_then(|i| i.description.as_ref()) {
+        sheet.write_string(row, 4, description)?;
+      }
+      sheet.write_number_with_format(row, 5, number(*amount), &money(currency(account).as_deref()))?;
+    }
+  }
+  sheet.autofilter(0, 0, row, 5)?;
+
+  headers(sheet, 0, 7, &["Number", "Account", "Description", "Type", "Sum"], &bold)?;
+  let mut row = 0;
+  for (t, _, accounts) in &gs.account_types {
+    for account in accounts {
+      row += 1;
+      if let Some(n) = account.number {
+        sheet.write_number(row, 7, n)?;
+      }
+      sheet.write_string(row, 8, &account.name)?;
+      if let Some(description) = &account.description {
+        sheet.write_string(row, 9, description)?;
+      }
+      sheet.write_string(row, 10, format!("{:?}", t))?;
+      sheet.write_number_with_format(row, 11, number(account.sum), &money(account.currency.as_deref()))?;
+    }
+  }
+  sheet.autofit();
+  Ok(())
+}
+
+/// Write the bookkeeping and its sums into a new workbook at the path,
+/// replacing any file there.
+pub fn export_xlsx(
+  real: &RealBookkeeping,
+  summary: &SummedBookkeeping,
+  path: &Path,
+) -> Result<(), String> {
+  let mut workbook = Workbook::new();
+  let mut taken = BTreeSet::new();
+  let sheet = workbook.add_worksheet();
+  sheet.set_name(sheet_name("Overview", &mut taken)).map_err(xlsx)?;
+  overview(sheet, summary).map_err(xlsx)?;
+  for (g, (name, gs)) in real.groupings.iter().zip(&summary.groupings) {
+    let sheet = workbook.add_worksheet();
+    sheet.set_name(sheet_name(name, &mut taken)).map_err(xlsx)?;
+    grouping(sheet, real, &g.transactions, gs).map_err(xlsx)?;
+  }
+  workbook.save(path).map_err(xlsx)
+}